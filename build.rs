@@ -0,0 +1,32 @@
+//! Build info for `version_line`: the git commit (`FALSH_GIT_COMMIT`, unless
+//! already set, say by a release script) and the enabled cargo features
+//! (`FALSH_FEATURES`).
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=FALSH_GIT_COMMIT");
+    // Moves of HEAD, so a new commit rebuilds with its hash.
+    for file in [".git/HEAD", ".git/logs/HEAD"] {
+        if Path::new(file).exists() {
+            println!("cargo:rerun-if-changed={}", file);
+        }
+    }
+    if env::var_os("FALSH_GIT_COMMIT").is_none() {
+        let output = Command::new("git").args(["rev-parse", "--short", "HEAD"]).output();
+        if let Ok(output) = output
+            && output.status.success()
+        {
+            let commit = String::from_utf8_lossy(&output.stdout);
+            println!("cargo:rustc-env=FALSH_GIT_COMMIT={}", commit.trim());
+        }
+    }
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|name| name.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=FALSH_FEATURES={}", features.join(","));
+}
@@ -0,0 +1,603 @@
+//! Arithmetic for `$(( ... ))`.
+//!
+//! Supports `+ - * / % **`, parentheses, unary `+ - ! ~`, the bitwise
+//! `& | ^ << >>`, the comparisons `== != < <= > >=` and the logical `&& ||`
+//! (which yield 1 or 0), `cond ? a : b`, and assignment with `=`, `op=` and
+//! `++`/`--`. Precedence follows C. Numbers may be written in hex (`0x1f`)
+//! or octal (`017`). Variables may be written bare (`i`) or as `$i`; unset
+//! or empty ones count as 0. The side not taken by `&&`, `||` and `?:` is
+//! not evaluated, so it neither assigns nor fails on division by zero.
+//!
+//! Integers are 64-bit and wrap. A number written with a point or an
+//! exponent (`1.5`, `.5`, `2e3`) is a float, and a float on either side
+//! makes the result one too, so `7/2` is 3 but `7/2.0` is 3.5. The bitwise
+//! operators take integers only. The functions `sqrt`, `abs` and `float`
+//! give floats (or, for `abs`, the argument's kind); `round`, `floor`,
+//! `ceil` and `int` give integers.
+
+/// Where arithmetic reads and assigns its variables.
+pub trait Variables {
+    fn get(&mut self, name: &str) -> Option<String>;
+    fn set(&mut self, name: &str, value: Number);
+}
+
+/// A value: an integer unless a float went into it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn as_float(self) -> f64 {
+        match self {
+            Number::Int(n) => n as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    fn is_true(self) -> bool {
+        match self {
+            Number::Int(n) => n != 0,
+            Number::Float(f) => f != 0.0,
+        }
+    }
+
+    /// The integer for an operator that has no float form.
+    fn int(self, op: &str) -> Result<i64, String> {
+        match self {
+            Number::Int(n) => Ok(n),
+            Number::Float(_) => Err(format!("arithmetic: '{}' needs integers", op)),
+        }
+    }
+}
+
+impl From<bool> for Number {
+    fn from(value: bool) -> Self {
+        Number::Int(i64::from(value))
+    }
+}
+
+/// Floats print with 12 significant digits, so `0.1 + 0.2` shows as
+/// `0.3`, and always with a point, so `3.0` does not pass for an integer.
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Number::Int(n) => write!(f, "{}", n),
+            Number::Float(x) if !x.is_finite() => write!(f, "{}", x),
+            Number::Float(x) => {
+                let rounded: f64 = format!("{:.11e}", x).parse().unwrap_or(x);
+                let text = rounded.to_string();
+                if text.contains('.') { write!(f, "{}", text) } else { write!(f, "{}.0", text) }
+            }
+        }
+    }
+}
+
+/// Evaluates `expr` against `vars`.
+pub fn eval(expr: &str, vars: &mut dyn Variables) -> Result<Number, String> {
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser { tokens, pos: 0, vars, skipping: false };
+    let value = parser.assignment()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(tok) => Err(format!("arithmetic: unexpected '{}'", tok)),
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Num(Number),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Longest first, so `==` is not read as `=` `=`.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**",
+    "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "++", "--",
+    "<<", ">>", "&&", "||",
+    "<", ">", "=", "+", "-", "*", "/", "%", "&", "|", "^", "(", ")", "!", "~", "?", ":",
+];
+
+const ASSIGN_OPS: &[&str] = &["=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "|=", "^="];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr;
+    while let Some(ch) = rest.chars().next() {
+        if ch.is_whitespace() {
+            rest = &rest[ch.len_utf8()..];
+        } else if ch.is_ascii_digit() || (ch == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let (num, end) = number(rest)?;
+            tokens.push(Token::Num(num));
+            rest = &rest[end..];
+        } else if ch.is_ascii_alphabetic() || ch == '_' || ch == '$' {
+            let body = rest.strip_prefix('$').unwrap_or(rest);
+            let end = body.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(body.len());
+            if end == 0 {
+                return Err("arithmetic: expected a variable name after '$'".into());
+            }
+            tokens.push(Token::Name(body[..end].to_string()));
+            rest = &body[end..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("arithmetic: unexpected '{}'", ch));
+        }
+    }
+    Ok(tokens)
+}
+
+/// The number at the start of `text` and its length.
+fn number(text: &str) -> Result<(Number, usize), String> {
+    let digits = |from: usize| from + text[from..].len() - text[from..].trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let mut end = digits(0);
+    let hex = text.starts_with("0x") || text.starts_with("0X");
+    let mut float = false;
+    if !hex && text[end..].starts_with('.') {
+        end = digits(end + 1);
+        float = true;
+    }
+    if !hex && text[end..].starts_with(['e', 'E']) {
+        let sign = usize::from(text[end + 1..].starts_with(['+', '-']));
+        let exponent = digits(end + 1 + sign);
+        if exponent > end + 1 + sign {
+            end = exponent;
+            float = true;
+        }
+    }
+    if float && !text[end..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '.') {
+        return text[..end].parse().map(|f| (Number::Float(f), end)).map_err(|_| format!("arithmetic: invalid number '{}'", &text[..end]));
+    }
+    let end = text.find(|c: char| !c.is_ascii_alphanumeric() && c != '.').unwrap_or(text.len());
+    let text = &text[..end];
+    let num = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None if text.len() > 1 && text.starts_with('0') => i64::from_str_radix(&text[1..], 8),
+        None => text.parse(),
+    };
+    let num = num.map_err(|_| format!("arithmetic: invalid number '{}'", text))?;
+    Ok((Number::Int(num), end))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a mut dyn Variables,
+    /// Set while parsing the side of `&&`, `||` or `?:` that is not taken.
+    skipping: bool,
+}
+
+/// Applies a binary operator, failing on division by zero and negative
+/// exponents.
+fn apply(op: &str, lhs: Number, rhs: Number) -> Result<Number, String> {
+    if matches!(op, "/" | "%") && rhs.as_float() == 0.0 {
+        return Err("arithmetic: division by zero".into());
+    }
+    let (Number::Int(lhs), Number::Int(rhs)) = (lhs, rhs) else {
+        let (lhs, rhs) = (lhs.as_float(), rhs.as_float());
+        return Ok(Number::Float(match op {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "**" => lhs.powf(rhs),
+            _ => return Err(format!("arithmetic: '{}' needs integers", op)),
+        }));
+    };
+    Ok(Number::Int(match op {
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "&" => lhs & rhs,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        _ => match u32::try_from(rhs) {
+            Ok(exp) => lhs.wrapping_pow(exp),
+            Err(_) => return Err("arithmetic: exponent less than 0".into()),
+        },
+    }))
+}
+
+/// `lhs op rhs` for the comparison operators. Integers compare exactly;
+/// otherwise both sides compare as floats.
+fn compare(op: &str, lhs: Number, rhs: Number) -> bool {
+    use std::cmp::Ordering::{Equal, Greater, Less};
+
+    let ord = match (lhs, rhs) {
+        (Number::Int(lhs), Number::Int(rhs)) => Some(lhs.cmp(&rhs)),
+        _ => lhs.as_float().partial_cmp(&rhs.as_float()),
+    };
+    match op {
+        "==" => ord == Some(Equal),
+        "!=" => ord != Some(Equal),
+        "<=" => matches!(ord, Some(Less | Equal)),
+        ">=" => matches!(ord, Some(Greater | Equal)),
+        "<" => ord == Some(Less),
+        _ => ord == Some(Greater),
+    }
+}
+
+/// Applies one of the named functions, or `None` when there is no such
+/// function.
+fn call(name: &str, arg: Number) -> Option<Result<Number, String>> {
+    let x = arg.as_float();
+    let value = match name {
+        "sqrt" if x < 0.0 => return Some(Err("arithmetic: sqrt of a negative number".into())),
+        "sqrt" => Number::Float(x.sqrt()),
+        "abs" => match arg {
+            Number::Int(n) => Number::Int(n.wrapping_abs()),
+            Number::Float(f) => Number::Float(f.abs()),
+        },
+        "float" => Number::Float(x),
+        "round" => Number::Int(x.round() as i64),
+        "floor" => Number::Int(x.floor() as i64),
+        "ceil" => Number::Int(x.ceil() as i64),
+        "int" => Number::Int(x.trunc() as i64),
+        _ => return None,
+    };
+    Some(Ok(value))
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    /// `name op= expr`, right-associative and lowest precedence.
+    fn assignment(&mut self) -> Result<Number, String> {
+        let target = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Name(name)), Some(Token::Op(op))) if ASSIGN_OPS.contains(op) => Some((name.clone(), *op)),
+            _ => None,
+        };
+        let Some((name, op)) = target else { return self.conditional() };
+        self.pos += 2;
+        let rhs = self.assignment()?;
+        let value = match op.strip_suffix('=').filter(|op| !op.is_empty()) {
+            Some(op) => {
+                let lhs = self.variable(&name)?;
+                self.apply(op, lhs, rhs)?
+            }
+            None => rhs,
+        };
+        self.assign(&name, value);
+        Ok(value)
+    }
+
+    /// `cond ? a : b`, right-associative.
+    fn conditional(&mut self) -> Result<Number, String> {
+        let cond = self.logical_or()?;
+        if self.eat(&["?"]).is_none() {
+            return Ok(cond);
+        }
+        let cond = cond.is_true();
+        let then = self.skip_unless(cond, Self::assignment)?;
+        if self.eat(&[":"]).is_none() {
+            return Err("arithmetic: missing ':' after '?'".into());
+        }
+        let otherwise = self.skip_unless(!cond, Self::conditional)?;
+        Ok(if cond { then } else { otherwise })
+    }
+
+    fn logical_or(&mut self) -> Result<Number, String> {
+        let mut lhs = self.logical_and()?;
+        while self.eat(&["||"]).is_some() {
+            let rhs = self.skip_unless(!lhs.is_true(), Self::logical_and)?;
+            lhs = Number::from(lhs.is_true() || rhs.is_true());
+        }
+        Ok(lhs)
+    }
+
+    fn logical_and(&mut self) -> Result<Number, String> {
+        let mut lhs = self.bit_or()?;
+        while self.eat(&["&&"]).is_some() {
+            let rhs = self.skip_unless(lhs.is_true(), Self::bit_or)?;
+            lhs = Number::from(lhs.is_true() && rhs.is_true());
+        }
+        Ok(lhs)
+    }
+
+    fn bit_or(&mut self) -> Result<Number, String> {
+        let mut lhs = self.bit_xor()?;
+        while let Some(op) = self.eat(&["|"]) {
+            let rhs = self.bit_xor()?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn bit_xor(&mut self) -> Result<Number, String> {
+        let mut lhs = self.bit_and()?;
+        while let Some(op) = self.eat(&["^"]) {
+            let rhs = self.bit_and()?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn bit_and(&mut self) -> Result<Number, String> {
+        let mut lhs = self.equality()?;
+        while let Some(op) = self.eat(&["&"]) {
+            let rhs = self.equality()?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn equality(&mut self) -> Result<Number, String> {
+        let mut lhs = self.relational()?;
+        while let Some(op) = self.eat(&["==", "!="]) {
+            let rhs = self.relational()?;
+            lhs = Number::from(compare(op, lhs, rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn relational(&mut self) -> Result<Number, String> {
+        let mut lhs = self.shift()?;
+        while let Some(op) = self.eat(&["<=", ">=", "<", ">"]) {
+            let rhs = self.shift()?;
+            lhs = Number::from(compare(op, lhs, rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn shift(&mut self) -> Result<Number, String> {
+        let mut lhs = self.additive()?;
+        while let Some(op) = self.eat(&["<<", ">>"]) {
+            let rhs = self.additive()?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn additive(&mut self) -> Result<Number, String> {
+        let mut lhs = self.multiplicative()?;
+        while let Some(op) = self.eat(&["+", "-"]) {
+            let rhs = self.multiplicative()?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn multiplicative(&mut self) -> Result<Number, String> {
+        let mut lhs = self.power()?;
+        while let Some(op) = self.eat(&["*", "/", "%"]) {
+            let rhs = self.power()?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    /// `a ** b`, right-associative and binding looser than unary minus.
+    fn power(&mut self) -> Result<Number, String> {
+        let lhs = self.unary()?;
+        match self.eat(&["**"]) {
+            Some(op) => {
+                let rhs = self.power()?;
+                self.apply(op, lhs, rhs)
+            }
+            None => Ok(lhs),
+        }
+    }
+
+    fn unary(&mut self) -> Result<Number, String> {
+        if let Some(op) = self.eat(&["++", "--"]) {
+            let name = self.name_after(op)?;
+            let value = self.step(&name, op)?;
+            self.assign(&name, value);
+            return Ok(value);
+        }
+        match self.eat(&["+", "-", "!", "~"]) {
+            Some("-") => Ok(match self.unary()? {
+                Number::Int(n) => Number::Int(n.wrapping_neg()),
+                Number::Float(f) => Number::Float(-f),
+            }),
+            Some("!") => Ok(Number::from(!self.unary()?.is_true())),
+            Some("~") => {
+                let value = self.unary()?;
+                if self.skipping { return Ok(Number::Int(0)); }
+                Ok(Number::Int(!value.int("~")?))
+            }
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Number, String> {
+        if self.eat(&["("]).is_some() {
+            return self.closing_paren();
+        }
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Num(n)) => Ok(n),
+            Some(Token::Name(name)) if self.eat(&["("]).is_some() => {
+                let arg = self.closing_paren()?;
+                if self.skipping {
+                    return Ok(Number::Int(0));
+                }
+                call(&name, arg).unwrap_or_else(|| Err(format!("arithmetic: {}: no such function", name)))
+            }
+            Some(Token::Name(name)) => {
+                let value = self.variable(&name)?;
+                if let Some(op) = self.eat(&["++", "--"]) {
+                    let stepped = self.step(&name, op)?;
+                    self.assign(&name, stepped);
+                }
+                Ok(value)
+            }
+            Some(tok) => Err(format!("arithmetic: unexpected '{}'", tok)),
+            None => Err("arithmetic: unexpected end of expression".into()),
+        }
+    }
+
+    /// The expression inside parentheses, after the `(`.
+    fn closing_paren(&mut self) -> Result<Number, String> {
+        let value = self.assignment()?;
+        match self.eat(&[")"]) {
+            Some(_) => Ok(value),
+            None => Err("arithmetic: missing ')'".into()),
+        }
+    }
+
+    fn name_after(&mut self, op: &str) -> Result<String, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(format!("arithmetic: '{}' needs a variable", op)),
+        }
+    }
+
+    /// `name` plus or minus one, for `++` and `--`.
+    fn step(&mut self, name: &str, op: &str) -> Result<Number, String> {
+        let value = self.variable(name)?;
+        apply(&op[..1], value, Number::Int(1))
+    }
+
+    /// Parses with `skipping` set unless `taken`, so the operand's
+    /// assignments and errors are dropped.
+    fn skip_unless(&mut self, taken: bool, operand: fn(&mut Self) -> Result<Number, String>) -> Result<Number, String> {
+        let outer = self.skipping;
+        self.skipping = outer || !taken;
+        let value = operand(self);
+        self.skipping = outer;
+        value
+    }
+
+    fn apply(&self, op: &str, lhs: Number, rhs: Number) -> Result<Number, String> {
+        if self.skipping {
+            return Ok(Number::Int(0));
+        }
+        apply(op, lhs, rhs)
+    }
+
+    fn assign(&mut self, name: &str, value: Number) {
+        if !self.skipping {
+            self.vars.set(name, value);
+        }
+    }
+
+    fn variable(&mut self, name: &str) -> Result<Number, String> {
+        let value = self.vars.get(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(Number::Int(0));
+        }
+        match value.parse() {
+            Ok(n) => Ok(Number::Int(n)),
+            Err(_) => value.parse().map(Number::Float).map_err(|_| format!("arithmetic: {}: '{}' is not a number", name, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    impl Variables for BTreeMap<String, String> {
+        fn get(&mut self, name: &str) -> Option<String> {
+            BTreeMap::get(self, name).cloned()
+        }
+
+        fn set(&mut self, name: &str, value: Number) {
+            self.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn int(expr: &str) -> i64 {
+        match eval(expr, &mut BTreeMap::new()) {
+            Ok(Number::Int(n)) => n,
+            other => panic!("{}: {:?}", expr, other),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(int("2 + 3 * 4"), 14);
+        assert_eq!(int("(2 + 3) * 4"), 20);
+        assert_eq!(int("2 * (3 + 4) - 10 / 3"), 11);
+        assert_eq!(int("-2 ** 2"), 4);
+        assert_eq!(int("10 - 4 - 3"), 3);
+        assert_eq!(int("17 % 5 + 1"), 3);
+    }
+
+    #[test]
+    fn comparisons_yield_one_or_zero() {
+        assert_eq!(int("3 < 4"), 1);
+        assert_eq!(int("3 >= 4"), 0);
+        assert_eq!(int("2 + 2 == 4"), 1);
+        assert_eq!(int("1 != 1"), 0);
+        assert_eq!(int("1 < 2 && 2 < 1"), 0);
+        assert_eq!(int("1 < 2 || 2 < 1"), 1);
+    }
+
+    #[test]
+    fn variables() {
+        let mut vars = vars(&[("i", "41"), ("empty", "")]);
+        assert_eq!(eval("i + 1", &mut vars), Ok(Number::Int(42)));
+        assert_eq!(eval("$i * 2", &mut vars), Ok(Number::Int(82)));
+        assert_eq!(eval("unset + empty + 1", &mut vars), Ok(Number::Int(1)));
+    }
+
+    #[test]
+    fn division_by_zero_fails() {
+        assert!(eval("1 / 0", &mut BTreeMap::new()).is_err());
+        assert!(eval("1 % (2 - 2)", &mut BTreeMap::new()).is_err());
+        // The branch not taken is not evaluated.
+        assert_eq!(int("0 && 1 / 0"), 0);
+    }
+
+    #[test]
+    fn assignment_updates_variables() {
+        let mut vars = vars(&[("count", "1")]);
+        assert_eq!(eval("count = count + 1", &mut vars), Ok(Number::Int(2)));
+        assert_eq!(eval("count += 5", &mut vars), Ok(Number::Int(7)));
+        assert_eq!(eval("count -= 2", &mut vars), Ok(Number::Int(5)));
+        assert_eq!(eval("count--", &mut vars), Ok(Number::Int(5)));
+        assert_eq!(eval("++count", &mut vars), Ok(Number::Int(5)));
+        assert_eq!(vars["count"], "5");
+    }
+
+    #[test]
+    fn post_increment_loop_terminates() {
+        let mut vars = vars(&[("i", "0")]);
+        let mut seen = Vec::new();
+        while eval("i < 5", &mut vars) == Ok(Number::Int(1)) {
+            seen.push(eval("i++", &mut vars).unwrap());
+            assert!(seen.len() <= 5, "loop did not stop");
+        }
+        assert_eq!(seen, [0, 1, 2, 3, 4].map(Number::Int));
+        assert_eq!(vars["i"], "5");
+    }
+}
@@ -0,0 +1,249 @@
+//! Brace expansion: `pre{a,b}post` and `{1..5}`.
+//!
+//! Runs on a parsed word before any other expansion, so only unquoted braces
+//! count: `"{a,b}"`, `\{a,b}` and the braces of `${VAR}` are left alone. A
+//! brace pair with neither a top-level comma nor a valid sequence stays as
+//! it is, as in bash. Sequences take integers (`{1..10}`, `{10..1..3}`,
+//! `{01..10}` pads to the wider end) or single letters (`{a..e}`); one of
+//! more than `MAX_SEQUENCE_LEN` items stays as it is too.
+
+use crate::parser::{self, Word, WordPart};
+
+/// One unit of a word: an unquoted character braces may act on, or a piece
+/// that passes through untouched.
+#[derive(Clone)]
+enum Atom {
+    Char(char),
+    Opaque(WordPart),
+}
+
+/// The words `word` expands to, in order; just `word` when it has no
+/// braces to expand.
+pub fn expand(word: &Word) -> Vec<Word> {
+    let atoms = atoms(word);
+    if !atoms.iter().any(|atom| matches!(atom, Atom::Char('{'))) {
+        return vec![word.clone()];
+    }
+    expand_from(atoms, 0)
+        .into_iter()
+        .map(|atoms| into_word(atoms, word.start, word.end))
+        .collect()
+}
+
+fn atoms(word: &Word) -> Vec<Atom> {
+    let mut atoms = Vec::new();
+    for part in &word.parts {
+        let WordPart::Bare(text) = part else {
+            atoms.push(Atom::Opaque(part.clone()));
+            continue;
+        };
+        let mut pos = 0;
+        while let Some(c) = text[pos..].chars().next() {
+            let end = match c {
+                '$' => parser::scan_dollar(text, pos).unwrap_or(text.len()),
+                '`' => parser::scan_backquote(text, pos).unwrap_or(text.len()),
+                _ => pos + c.len_utf8(),
+            };
+            if end - pos > 1 {
+                atoms.push(Atom::Opaque(WordPart::Bare(text[pos..end].to_string())));
+            } else {
+                atoms.push(Atom::Char(c));
+            }
+            pos = end;
+        }
+    }
+    atoms
+}
+
+/// Expands the first expandable brace pair at or after `from`, then the
+/// rest of each result.
+fn expand_from(atoms: Vec<Atom>, from: usize) -> Vec<Vec<Atom>> {
+    for open in from..atoms.len() {
+        if !matches!(atoms[open], Atom::Char('{')) {
+            continue;
+        }
+        let Some((close, commas)) = find_close(&atoms, open) else { continue };
+        let items = if commas.is_empty() {
+            match sequence(&atoms[open + 1..close]) {
+                Some(items) => items,
+                None => continue,
+            }
+        } else {
+            let mut items = Vec::new();
+            let mut start = open + 1;
+            for comma in commas.into_iter().chain([close]) {
+                items.push(atoms[start..comma].to_vec());
+                start = comma + 1;
+            }
+            items
+        };
+        let mut out = Vec::new();
+        for item in items {
+            let mut next = atoms[..open].to_vec();
+            next.extend(item);
+            next.extend_from_slice(&atoms[close + 1..]);
+            out.extend(expand_from(next, open));
+        }
+        return out;
+    }
+    vec![atoms]
+}
+
+/// The `}` matching the `{` at `open` and the commas directly inside it.
+fn find_close(atoms: &[Atom], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (idx, atom) in atoms.iter().enumerate().skip(open) {
+        match atom {
+            Atom::Char('{') => depth += 1,
+            Atom::Char('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((idx, commas));
+                }
+            }
+            Atom::Char(',') if depth == 1 => commas.push(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The most items a sequence may have; a longer one is left as written
+/// rather than exhausting memory on something like `{1..99999999999}`.
+const MAX_SEQUENCE_LEN: u64 = 100_000;
+
+/// The items of `a..b` or `a..b..step`, for integers or single letters.
+fn sequence(atoms: &[Atom]) -> Option<Vec<Vec<Atom>>> {
+    let mut text = String::new();
+    for atom in atoms {
+        match atom {
+            Atom::Char(c) => text.push(*c),
+            Atom::Opaque(_) => return None,
+        }
+    }
+    let ends: Vec<&str> = text.split("..").collect();
+    let (first, last, step) = match ends.as_slice() {
+        [first, last] => (*first, *last, 1),
+        [first, last, step] => (*first, *last, step.parse::<i64>().ok()?.checked_abs()?.max(1)),
+        _ => return None,
+    };
+    if let (Ok(a), Ok(b)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let padded = |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+        let width = if padded(first) || padded(last) { first.len().max(last.len()) } else { 0 };
+        let items = range(a, b, step)?
+            .map(|n| match n < 0 {
+                true => format!("-{:0>1$}", -n, width.saturating_sub(1)),
+                false => format!("{:0>1$}", n, width),
+            })
+            .map(|item| item.chars().map(Atom::Char).collect())
+            .collect();
+        Some(items)
+    } else {
+        let letter = |s: &str| {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => Some(c as i64),
+                _ => None,
+            }
+        };
+        // `{Z..a}` passes `[`, `\`, `` ` `` and the like, which come out as
+        // themselves, so they are quoted.
+        let items = range(letter(first)?, letter(last)?, step)?
+            .map(|n| char::from(n as u8))
+            .map(|c| match c.is_ascii_alphabetic() {
+                true => vec![Atom::Char(c)],
+                false => vec![Atom::Opaque(WordPart::Single(c.to_string()))],
+            })
+            .collect();
+        Some(items)
+    }
+}
+
+/// `a` to `b` inclusive in steps of `step`, counting down when `b < a`;
+/// `None` past `MAX_SEQUENCE_LEN` items.
+fn range(a: i64, b: i64, step: i64) -> Option<impl Iterator<Item = i64>> {
+    let count = a.abs_diff(b) / step as u64;
+    if count >= MAX_SEQUENCE_LEN {
+        return None;
+    }
+    let step = if b < a { -step } else { step };
+    Some((0..=count as i64).map(move |i| a + i * step))
+}
+
+fn into_word(atoms: Vec<Atom>, start: usize, end: usize) -> Word {
+    let mut parts = Vec::new();
+    let mut bare = String::new();
+    for atom in atoms {
+        match atom {
+            Atom::Char(c) => bare.push(c),
+            Atom::Opaque(WordPart::Bare(text)) => bare.push_str(&text),
+            Atom::Opaque(part) => {
+                if !bare.is_empty() {
+                    parts.push(WordPart::Bare(std::mem::take(&mut bare)));
+                }
+                parts.push(part);
+            }
+        }
+    }
+    if !bare.is_empty() {
+        parts.push(WordPart::Bare(bare));
+    }
+    Word { parts, start, end }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The words `text` expands to, with quotes removed.
+    fn expand_text(text: &str) -> Vec<String> {
+        let list = parser::parse(text).unwrap();
+        let word = &list.items[0].1.commands[0].words[0];
+        expand(word).iter().map(Word::unquoted).collect()
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(expand_text("src/{lib,bin}"), ["src/lib", "src/bin"]);
+        assert_eq!(expand_text("a{,b}"), ["a", "ab"]);
+        assert_eq!(expand_text("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+    }
+
+    #[test]
+    fn nesting() {
+        assert_eq!(expand_text("x{a,b{1,2},c}y"), ["xay", "xb1y", "xb2y", "xcy"]);
+        assert_eq!(expand_text("{a,{b,{c,d}}}"), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(expand_text("{1..5}"), ["1", "2", "3", "4", "5"]);
+        assert_eq!(expand_text("{10..1..3}"), ["10", "7", "4", "1"]);
+        assert_eq!(expand_text("{-2..1}"), ["-2", "-1", "0", "1"]);
+        assert_eq!(expand_text("{01..10}"), ["01", "02", "03", "04", "05", "06", "07", "08", "09", "10"]);
+        assert_eq!(expand_text("{a..e..2}"), ["a", "c", "e"]);
+        assert_eq!(expand_text("{e..c}"), ["e", "d", "c"]);
+        assert_eq!(expand_text("{Z..a}"), ["Z", "[", "\\", "]", "^", "_", "`", "a"]);
+        assert_eq!(expand_text("{X..b..2}"), ["X", "Z", "\\", "^", "`", "b"]);
+    }
+
+    #[test]
+    fn left_alone() {
+        assert_eq!(expand_text("\"{a,b}\""), ["{a,b}"]);
+        assert_eq!(expand_text("'{a,b}'"), ["{a,b}"]);
+        assert_eq!(expand_text("\\{a,b}"), ["{a,b}"]);
+        assert_eq!(expand_text("${HOME}"), ["${HOME}"]);
+        assert_eq!(expand_text("{a}"), ["{a}"]);
+        assert_eq!(expand_text("{1..b}"), ["{1..b}"]);
+        assert_eq!(expand_text("a{1..99999999999}b"), ["a{1..99999999999}b"]);
+        assert_eq!(expand_text("{a,b"), ["{a,b"]);
+    }
+
+    #[test]
+    fn quoted_items_stay_quoted() {
+        let list = parser::parse("{'*',b}").unwrap();
+        let words = expand(&list.items[0].1.commands[0].words[0]);
+        assert_eq!(words[0].parts, [WordPart::Single("*".to_string())]);
+    }
+}
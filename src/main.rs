@@ -1,14 +1,25 @@
+mod arith;
+mod brace;
+mod parser;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Once;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
 
-use glob::glob;
-use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use glob::{glob, Pattern};
+use parser::{Connector, List, Pipeline, RedirectOp, SimpleCommand, Word, WordPart};
+use rustyline::completion::{Completer, Pair};
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
-use rustyline::validate::Validator;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::error::ReadlineError;
 use rustyline::{Editor, Helper, Context};
 use crossterm::{
@@ -17,13 +28,106 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
 };
-use dirs;
+
+/// `println!` for builtin output: a reader that went away (`falsh | head`)
+/// is not an error worth reporting, anything else goes to stderr.
+macro_rules! outln {
+    ($($arg:tt)*) => {{
+        if let Err(e) = writeln!(io::stdout(), $($arg)*) {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                eprintln!("falsh: write error: {}", e);
+            }
+        }
+    }};
+}
+
+/// `print!` for builtin output, with the same error handling as `outln!`.
+macro_rules! out {
+    ($($arg:tt)*) => {{
+        if let Err(e) = write!(io::stdout(), $($arg)*) {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                eprintln!("falsh: write error: {}", e);
+            }
+        }
+    }};
+}
+
+/// Builtin names offered by completion and command correction.
+const BUILTINS: &[&str] = &[
+    "cd",
+    "pwd",
+    "addToPath",
+    "listPaths",
+    "exit",
+    "echo",
+    "printf",
+    "read",
+    "test",
+    "type",
+    "which",
+    "command",
+    "builtin",
+    "export",
+    "unset",
+    "set",
+    "repeat",
+    "exec",
+    "source",
+    "please",
+    "shift",
+    "alias",
+    "unalias",
+    "complete",
+    "trap",
+    "version",
+];
+
+/// One-line version for `--version` and the `version` builtin: `falsh x.y.z`,
+/// then `commit=<hash>` when the build knew it (see build.rs) and
+/// `features=` with the enabled cargo features, comma-separated.
+fn version_line() -> String {
+    let mut line = format!("falsh {}", env!("CARGO_PKG_VERSION"));
+    if let Some(commit) = option_env!("FALSH_GIT_COMMIT").filter(|commit| !commit.is_empty()) {
+        line.push_str(&format!(" commit={}", commit));
+    }
+    line.push_str(&format!(" features={}", env!("FALSH_FEATURES")));
+    line
+}
+
+/// Shows long builtin output through `$PAGER` (default `less`) when stdout
+/// is a terminal and the text is taller than it; otherwise prints it as is.
+fn page_output(text: &str) {
+    let rows = crossterm::terminal::size().map(|(_, rows)| rows as usize).unwrap_or(usize::MAX);
+    if io::stdout().is_terminal() && text.lines().count() > rows {
+        let pager = env::var("PAGER").ok().filter(|p| !p.trim().is_empty()).unwrap_or_else(|| "less".into());
+        let mut words = pager.split_whitespace();
+        let spawned = Command::new(words.next().unwrap_or("less"))
+            .args(words)
+            .stdin(Stdio::piped())
+            .spawn();
+        if let Ok(mut child) = spawned {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The pager may quit before reading everything.
+                stdin.write_all(text.as_bytes()).ok();
+            }
+            child.wait().ok();
+            return;
+        }
+    }
+    outln!("{}", text.trim_end_matches('\n'));
+}
 
 /// ------------------ HELPER FOR AUTOCOMPLETE ------------------
+/// File names are listed relative to the process cwd on every Tab press, so
+/// completions follow `cd` without any cached state here.
 struct FalshHelper {
-    file_comp: FilenameCompleter,
     builtins: Vec<String>,
+    /// Word lists registered with `complete -W`, shared with the shell.
+    completions: Completions,
 }
+
+/// Per-command word lists for Tab completion, keyed by command name.
+type Completions = Rc<RefCell<BTreeMap<String, Vec<String>>>>;
 impl Helper for FalshHelper {}
 impl Completer for FalshHelper {
     type Candidate = Pair;
@@ -31,16 +135,25 @@ impl Completer for FalshHelper {
         &self,
         line: &str,
         pos: usize,
-        ctx: &Context<'_>,
+        _ctx: &Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        let (start, word) = match line[..pos].rfind(|c: char| c.is_whitespace()) {
-            Some(idx) => (idx + 1, &line[idx + 1..pos]),
-            None => (0, &line[..pos]),
-        };
+        let (start, word, quote) = completion_word(&line[..pos]);
         let mut out = Vec::new();
+        if start > 0 {
+            let cmd = line.split_whitespace().next().unwrap_or("");
+            if let Some(words) = self.completions.borrow().get(cmd) {
+                for w in words.iter().filter(|w| w.starts_with(&word)) {
+                    out.push(Pair {
+                        display: w.clone(),
+                        replacement: quote_completion(w, quote, true),
+                    });
+                }
+                return Ok((start, out));
+            }
+        }
         if start == 0 {
             for b in &self.builtins {
-                if b.starts_with(word) {
+                if b.starts_with(&word) {
                     out.push(Pair {
                         display: b.clone(),
                         replacement: b.clone(),
@@ -48,292 +161,3748 @@ impl Completer for FalshHelper {
                 }
             }
         }
-        let (_, mut files) = self.file_comp.complete(line, pos, ctx)?;
-        out.append(&mut files);
+        for (name, path, is_dir) in complete_files(&word) {
+            out.push(Pair {
+                display: name,
+                replacement: quote_completion(&path, quote, !is_dir),
+            });
+        }
         Ok((start, out))
     }
 }
+
+/// The word the cursor is in at the end of `line`: where it starts, its
+/// text with quotes and backslashes removed, and the quote still open, if
+/// any. Words end at whitespace and at `|`, `&`, `;`, `<`, `>`, `(` and `)`.
+fn completion_word(line: &str) -> (usize, String, Option<char>) {
+    let mut start = 0;
+    let mut word = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        if escaped {
+            word.push(c);
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"') | None, '\\') => escaped = true,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() || "|&;<>()".contains(c) => {
+                start = idx + c.len_utf8();
+                word.clear();
+            }
+            (None, c) => word.push(c),
+        }
+    }
+    (start, word, quote)
+}
+
+/// Files whose path starts with `prefix`, as (name to show, path to
+/// insert, is a directory). Directories get a trailing `/`, and a leading
+/// `~/` is looked up in the home directory but kept in the path.
+fn complete_files(prefix: &str) -> Vec<(String, String, bool)> {
+    let (dir_part, name_prefix) = match prefix.rfind('/') {
+        Some(idx) => prefix.split_at(idx + 1),
+        None => ("", prefix),
+    };
+    let dir = match dir_part.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().map(|home| home.join(rest)),
+        None if dir_part.is_empty() => Some(PathBuf::from(".")),
+        None => Some(PathBuf::from(dir_part)),
+    };
+    let Some(entries) = dir.and_then(|dir| fs::read_dir(dir).ok()) else { return Vec::new() };
+    let mut files: Vec<(String, String, bool)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(name_prefix) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            let name = if is_dir { format!("{}/", name) } else { name };
+            Some((name.clone(), format!("{}{}", dir_part, name), is_dir))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Quotes a completion the way the word was being typed: inside the open
+/// `'` or `"`, closed again once `done`, or otherwise with a backslash
+/// before each character the parser would treat specially.
+fn quote_completion(text: &str, quote: Option<char>, done: bool) -> String {
+    match quote {
+        Some(open) => {
+            let body = if open == '\'' {
+                text.replace('\'', "'\\''")
+            } else {
+                text.chars().fold(String::new(), |mut out, c| {
+                    if matches!(c, '"' | '\\' | '$' | '`') { out.push('\\'); }
+                    out.push(c);
+                    out
+                })
+            };
+            format!("{open}{body}{}", if done { open.to_string() } else { String::new() })
+        }
+        // `~` stays bare so a typed `~/` still means home.
+        None => text.chars().fold(String::new(), |mut out, c| {
+            if c.is_whitespace() || "|&;<>()$`\\\"'*?[]#{}!".contains(c) { out.push('\\'); }
+            out.push(c);
+            out
+        }),
+    }
+}
+
 impl Hinter for FalshHelper {
     type Hint = String;
     fn hint(&self, _: &str, _: usize, _: &Context<'_>) -> Option<String> { None }
 }
 impl Highlighter for FalshHelper {}
-impl Validator for FalshHelper {}
-/// -------------------------------------------------------------
+impl Validator for FalshHelper {
+    /// Enter on an unfinished line (open quote, trailing `|`, pending
+    /// here-document, ...) starts a new line in the editor instead of
+    /// running it.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if parser::incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+// -------------------------------------------------------------
+
+/// Expands `$NAME`, `${NAME}`, `$?`, the positional `$1`, `$#`, `$@`,
+/// arithmetic `$(( ... ))` and command substitution `$( ... )` in the text
+/// of an unquoted or double-quoted word part. Unset variables expand to
+/// nothing.
+fn expand_vars(shell: &mut Shell, input: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '$' => match chars.peek().copied() {
+                Some('?') => {
+                    chars.next();
+                    out.push_str(&shell.last_status.to_string());
+                }
+                Some(c) if c.is_ascii_digit() || "#@*$!".contains(c) => {
+                    chars.next();
+                    out.push_str(&shell.lookup_var(&c.to_string()).unwrap_or_default());
+                }
+                Some('(') if chars.clone().nth(1) == Some('(') => {
+                    chars.next();
+                    chars.next();
+                    let expr = take_arith_expr(&mut chars)?;
+                    let value = arith::eval(&expr, shell)?;
+                    out.push_str(&value.to_string());
+                }
+                Some('(') => {
+                    chars.next();
+                    let command = take_enclosed(&mut chars, '(', ')')?;
+                    out.push_str(&capture_output(shell, &command)?);
+                }
+                Some('{') => {
+                    chars.next();
+                    let body = take_enclosed(&mut chars, '{', '}')?;
+                    out.push_str(&expand_parameter(shell, &body)?);
+                }
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                    let mut name = String::new();
+                    while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                        name.push(c);
+                        chars.next();
+                    }
+                    out.push_str(&shell.lookup_var(&name).unwrap_or_default());
+                }
+                _ => out.push('$'),
+            },
+            '`' => {
+                let command = take_backquoted(&mut chars)?;
+                out.push_str(&capture_output(shell, &command)?);
+            }
+            _ => out.push(ch),
+        }
+    }
+    Ok(out)
+}
+
+/// Whether `name` can follow `${`: a variable name, a positional number
+/// or one of the special parameters.
+fn is_var_name(name: &str) -> bool {
+    parser::is_name(name)
+        || (!name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()))
+        || matches!(name, "?" | "#" | "@" | "*" | "$" | "!")
+}
+
+/// Reads the body of `$(( ... ))` up to the closing `))`, the opening
+/// `$((` already consumed.
+fn take_arith_expr(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<String, String> {
+    let mut expr = String::new();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                if chars.next() == Some(')') { return Ok(expr); }
+                break;
+            }
+            ')' => depth -= 1,
+            _ => {}
+        }
+        expr.push(c);
+    }
+    Err("Syntax error: unterminated '$(('".into())
+}
+
+/// Reads the body of `$( ... )` or `${ ... }` up to the `close` matching
+/// the already consumed `open`. Brackets inside quotes do not count.
+fn take_enclosed(chars: &mut std::iter::Peekable<std::str::Chars<'_>>, open: char, close: char) -> Result<String, String> {
+    let mut body = String::new();
+    let mut depth = 0;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escaped = false;
+    for c in chars.by_ref() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if !in_single => escaped = true,
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            c if c == open && !in_single && !in_double => depth += 1,
+            c if c == close && !in_single && !in_double => {
+                if depth == 0 { return Ok(body); }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        body.push(c);
+    }
+    Err(format!("Syntax error: unterminated '${}'", open))
+}
+
+/// Expands the inside of `${ ... }`: a parameter name, optionally followed
+/// by `-`, `=`, `+` or `?` and a word, each led by `:` to treat an empty
+/// value like an unset one.
+///
+/// - `${VAR-word}`: the value, or `word` when unset.
+/// - `${VAR=word}`: the same, also assigning `word` to `VAR`.
+/// - `${VAR+word}`: `word` when set, nothing otherwise.
+/// - `${VAR?word}`: the value, or fails with `word` as the message.
+///
+/// `${VAR#pat}` and `${VAR##pat}` remove the shortest or longest prefix
+/// matching the glob `pat`, `${VAR%pat}` and `${VAR%%pat}` the shortest or
+/// longest suffix, and `${#VAR}` is the value's length in characters.
+fn expand_parameter(shell: &mut Shell, body: &str) -> Result<String, String> {
+    let bad = || format!("${{{}}}: bad substitution", body);
+    if let Some(name) = body.strip_prefix('#').filter(|name| !name.is_empty() && is_var_name(name)) {
+        return Ok(shell.lookup_var(name).unwrap_or_default().chars().count().to_string());
+    }
+    let name_len = match body.chars().next() {
+        Some(c) if "?#@*$!".contains(c) => 1,
+        Some(c) if c.is_ascii_digit() => body.find(|c: char| !c.is_ascii_digit()).unwrap_or(body.len()),
+        _ => body.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(body.len()),
+    };
+    let (name, rest) = body.split_at(name_len);
+    if !is_var_name(name) {
+        return Err(bad());
+    }
+    let value = shell.lookup_var(name);
+    if rest.is_empty() {
+        return Ok(value.unwrap_or_default());
+    }
+    if let Some(op) = ["##", "#", "%%", "%"].into_iter().find(|op| rest.starts_with(op)) {
+        let pattern = expand_operand(shell, &rest[op.len()..], true)?;
+        let pattern = Pattern::new(&pattern).map_err(|e| format!("${{{}}}: {}", body, e))?;
+        return Ok(trim_match(&value.unwrap_or_default(), &pattern, op).to_string());
+    }
+    let (colon, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let mut op_chars = rest.chars();
+    let op = op_chars.next().filter(|c| "-=+?".contains(*c)).ok_or_else(bad)?;
+    let word = op_chars.as_str();
+    let value = value.filter(|v| !(colon && v.is_empty()));
+    match (op, value) {
+        ('-', None) => expand_operand(shell, word, false),
+        ('=', None) => {
+            if !parser::is_name(name) {
+                return Err(format!("${}: cannot assign in this way", name));
+            }
+            let word = expand_operand(shell, word, false)?;
+            shell.set_var(name, &word);
+            Ok(word)
+        }
+        ('+', Some(_)) => expand_operand(shell, word, false),
+        ('+', None) => Ok(String::new()),
+        // `?`
+        (_, None) => {
+            let message = expand_operand(shell, word, false)?;
+            let message = if message.is_empty() { "parameter null or not set".to_string() } else { message };
+            Err(format!("{}: {}", name, message))
+        }
+        (_, Some(value)) => Ok(value),
+    }
+}
+
+/// `value` without the prefix (`#`, `##`) or suffix (`%`, `%%`) matching
+/// `pattern`, the doubled forms taking the longest match.
+fn trim_match<'a>(value: &'a str, pattern: &Pattern, op: &str) -> &'a str {
+    let mut cuts: Vec<usize> = value.char_indices().map(|(idx, _)| idx).chain([value.len()]).collect();
+    let longest = op.len() == 2;
+    if op.starts_with('#') {
+        if longest { cuts.reverse(); }
+        cuts.into_iter().find(|&cut| pattern.matches(&value[..cut])).map_or(value, |cut| &value[cut..])
+    } else {
+        if !longest { cuts.reverse(); }
+        cuts.into_iter().find(|&cut| pattern.matches(&value[cut..])).map_or(value, |cut| &value[..cut])
+    }
+}
+
+/// Expands the word inside `${VAR:-word}` and friends, removing its quotes.
+/// For a `pattern`, quoted text is escaped so it only matches literally.
+fn expand_operand(shell: &mut Shell, word: &str, pattern: bool) -> Result<String, String> {
+    let quote = |text: &str| if pattern { Pattern::escape(text) } else { text.to_string() };
+    let mut out = String::new();
+    let mut bare = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                out.push_str(&expand_vars(shell, &std::mem::take(&mut bare))?);
+                out.push_str(&quote(&chars.by_ref().take_while(|&c| c != '\'').collect::<String>()));
+            }
+            '"' => {
+                out.push_str(&expand_vars(shell, &std::mem::take(&mut bare))?);
+                let mut quoted = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => quoted.extend(['\\'].into_iter().chain(chars.next())),
+                        c => quoted.push(c),
+                    }
+                }
+                out.push_str(&quote(&expand_vars(shell, &quoted)?));
+            }
+            '\\' => {
+                out.push_str(&expand_vars(shell, &std::mem::take(&mut bare))?);
+                out.push_str(&quote(&chars.next().map(String::from).unwrap_or_default()));
+            }
+            c => bare.push(c),
+        }
+    }
+    out.push_str(&expand_vars(shell, &bare)?);
+    Ok(out)
+}
+
+/// Reads the body of `` `...` `` up to the closing backquote, the opening
+/// one already consumed. A backslash before `` ` ``, `$` or `\` is dropped
+/// so nested backquotes can be written as `` \` ``.
+fn take_backquoted(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<String, String> {
+    let mut command = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => return Ok(command),
+            '\\' => match chars.next() {
+                Some(escaped @ ('`' | '$' | '\\')) => command.push(escaped),
+                Some(other) => {
+                    command.push('\\');
+                    command.push(other);
+                }
+                None => command.push('\\'),
+            },
+            _ => command.push(c),
+        }
+    }
+    Err("Syntax error: unterminated '`'".into())
+}
+
+/// Runs `command` in this shell with stdout captured, for `$( ... )`.
+/// Output that is not valid UTF-8 is converted lossily (bad bytes become
+/// U+FFFD) rather than failing, and trailing newlines are trimmed.
+///
+/// Programs started here get the current environment, so `export`ed
+/// variables reach them while shell-only variables do not.
+#[cfg(unix)]
+fn capture_output(shell: &mut Shell, command: &str) -> Result<String, String> {
+    let (result, bytes) = collect_stdout(|| execute_line(shell, command))
+        .map_err(|e| format!("command substitution: {}", e))?;
+    shell.last_status = result?;
+    Ok(String::from_utf8_lossy(&bytes).trim_end_matches('\n').to_string())
+}
+
+/// Runs `f` with the shell's stdout going into memory, returning what it
+/// wrote. A thread drains the pipe so any amount of output fits.
+#[cfg(unix)]
+fn collect_stdout<T>(f: impl FnOnce() -> T) -> Result<(T, Vec<u8>), String> {
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+    // The read end must not leak into the children we spawn.
+    unsafe { libc::fcntl(read_fd, libc::F_SETFD, libc::FD_CLOEXEC); }
+    let mut reader = unsafe { File::from_raw_fd(read_fd) };
+    let collector = std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map(|_| bytes)
+    });
+
+    io::stdout().flush().ok();
+    let saved_stdout = unsafe { libc::dup(1) };
+    unsafe {
+        libc::dup2(write_fd, 1);
+        libc::close(write_fd);
+    }
+    let result = f();
+    io::stdout().flush().ok();
+    unsafe {
+        libc::dup2(saved_stdout, 1);
+        libc::close(saved_stdout);
+    }
+
+    let bytes = collector.join()
+        .map_err(|_| "reader panicked".to_string())?
+        .map_err(|e| e.to_string())?;
+    Ok((result, bytes))
+}
+
+#[cfg(not(unix))]
+fn collect_stdout<T>(_f: impl FnOnce() -> T) -> Result<(T, Vec<u8>), String> {
+    Err("not supported on this platform".into())
+}
+
+#[cfg(not(unix))]
+fn capture_output(_shell: &mut Shell, _command: &str) -> Result<String, String> {
+    Err("command substitution is not supported on this platform".into())
+}
+
+/// A word after expansion, kept alongside a glob pattern in which the
+/// characters that came from quotes are escaped.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    /// Whether an unquoted part holds `*`, `?` or `[`.
+    glob: bool,
+}
+
+impl Field {
+    fn push_quoted(&mut self, text: &str) {
+        self.text.push_str(text);
+        // Parentheses are escaped too, so a quoted `(.)` is no qualifier.
+        self.pattern.push_str(&Pattern::escape(text).replace('(', "[(]").replace(')', "[)]"));
+    }
+
+    fn push_bare(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.pattern.push_str(&bracket_pattern(text));
+        self.glob |= text.contains(['*', '?', '[']);
+    }
+}
+
+/// Rewrites the `[...]` expressions in unquoted text into the glob crate's
+/// syntax: `[^a]` becomes `[!a]`, and POSIX classes like `[[:digit:]]`
+/// become ranges.
+fn bracket_pattern(text: &str) -> String {
+    const CLASSES: &[(&str, &str)] = &[
+        ("[:alnum:]", "a-zA-Z0-9"),
+        ("[:alpha:]", "a-zA-Z"),
+        ("[:digit:]", "0-9"),
+        ("[:lower:]", "a-z"),
+        ("[:upper:]", "A-Z"),
+        ("[:space:]", " \t\n\r\x0b\x0c"),
+        ("[:xdigit:]", "0-9a-fA-F"),
+    ];
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('[') {
+        out.push_str(&rest[..open]);
+        let mut body = &rest[open + 1..];
+        out.push('[');
+        if let Some(negated) = body.strip_prefix(['^', '!']) {
+            out.push('!');
+            body = negated;
+        }
+        // A `]` right at the start is a member, not the end.
+        if let Some(after) = body.strip_prefix(']') {
+            out.push(']');
+            body = after;
+        }
+        loop {
+            if let Some((class, range)) = CLASSES.iter().find(|(class, _)| body.starts_with(class)) {
+                out.push_str(range);
+                body = &body[class.len()..];
+                continue;
+            }
+            match body.chars().next() {
+                Some(c) => {
+                    out.push(c);
+                    body = &body[c.len_utf8()..];
+                    if c == ']' { break; }
+                }
+                None => break,
+            }
+        }
+        rest = body;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Expands a word into fields. Unquoted expansions are split on whitespace,
+/// so one word can become several arguments or none at all; quoted parts
+/// always stay within a single field.
+fn expand_word(shell: &mut Shell, word: &Word) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    let mut current: Option<Field> = None;
+    for (idx, part) in word.parts.iter().enumerate() {
+        match part {
+            WordPart::Single(text) => current.get_or_insert_default().push_quoted(text),
+            WordPart::Process { command, output } => {
+                let path = process_substitution(shell, command, *output)?;
+                current.get_or_insert_default().push_quoted(&path);
+            }
+            WordPart::Double(text) => {
+                let text = expand_vars(shell, text)?;
+                current.get_or_insert_default().push_quoted(&text);
+            }
+            WordPart::Bare(text) => {
+                let text = match expand_tilde(text, word.parts.len() == 1).filter(|_| idx == 0) {
+                    Some((dir, rest)) => {
+                        current.get_or_insert_default().push_quoted(&dir);
+                        rest
+                    }
+                    None => text,
+                };
+                let text = expand_vars(shell, text)?;
+                if text.starts_with(char::is_whitespace) {
+                    fields.extend(current.take());
+                }
+                let mut pieces = text.split_whitespace().peekable();
+                while let Some(piece) = pieces.next() {
+                    current.get_or_insert_default().push_bare(piece);
+                    if pieces.peek().is_some() {
+                        fields.extend(current.take());
+                    }
+                }
+                if text.ends_with(char::is_whitespace) {
+                    fields.extend(current.take());
+                }
+            }
+        }
+    }
+    fields.extend(current);
+    Ok(fields)
+}
+
+/// Splits a leading `~`, `~user`, `~+` (the current directory) or `~-`
+/// (the previous one) off unquoted text, up to the first `/`, returning the
+/// directory it names and the rest of the text. `whole` tells whether the
+/// text is the entire word: `~"user"` is not a tilde prefix.
+fn expand_tilde(text: &str, whole: bool) -> Option<(String, &str)> {
+    let rest = text.strip_prefix('~')?;
+    let end = rest.find('/').or(whole.then_some(rest.len()))?;
+    let (user, rest) = rest.split_at(end);
+    let dir = match user {
+        "" => env::var("HOME").ok().filter(|home| !home.is_empty())
+            .or_else(|| dirs::home_dir().map(|home| home.display().to_string()))?,
+        "+" => env::var("PWD").ok()?,
+        "-" => env::var("OLDPWD").ok()?,
+        user => user_home(user)?,
+    };
+    Some((dir, rest))
+}
+
+/// The home directory of `user` from the password database.
+#[cfg(unix)]
+fn user_home(user: &str) -> Option<String> {
+    let name = std::ffi::CString::new(user).ok()?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return None;
+    }
+    let dir = unsafe { std::ffi::CStr::from_ptr((*entry).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn user_home(_user: &str) -> Option<String> {
+    None
+}
+
+/// Expands a word to exactly one string, without splitting or globbing,
+/// as for assignment values and redirection targets.
+fn expand_single(shell: &mut Shell, word: &Word) -> Result<String, String> {
+    let mut out = String::new();
+    for (idx, part) in word.parts.iter().enumerate() {
+        match part {
+            WordPart::Single(text) => out.push_str(text),
+            WordPart::Bare(text) if idx == 0 && let Some((dir, rest)) = expand_tilde(text, word.parts.len() == 1) => {
+                out.push_str(&dir);
+                out.push_str(&expand_vars(shell, rest)?);
+            }
+            WordPart::Double(text) | WordPart::Bare(text) => out.push_str(&expand_vars(shell, text)?),
+            WordPart::Process { command, output } => out.push_str(&process_substitution(shell, command, *output)?),
+        }
+    }
+    Ok(out)
+}
+
+/// Starts `command` for `<(command)`, or `>(command)` when `output` is set,
+/// and returns the `/dev/fd` path that reaches it. The command runs in a
+/// separate `falsh -c`, so it sees exported variables but not shell-only
+/// ones. Its descriptor stays open until the pipeline using it finishes
+/// (see `finish_substitutions`).
+#[cfg(unix)]
+fn process_substitution(shell: &mut Shell, command: &str, output: bool) -> Result<String, String> {
+    use std::os::fd::{AsRawFd, OwnedFd};
+
+    let args = subshell_args(shell, command)?;
+    let mut cmd = Command::new(&args[0]);
+    cmd.args(&args[1..]);
+    if output { cmd.stdin(Stdio::piped()); } else { cmd.stdout(Stdio::piped()); }
+    let mut child = cmd.spawn().map_err(|e| format!("process substitution: {}", e))?;
+    let end: OwnedFd = match (child.stdin.take(), child.stdout.take()) {
+        (Some(stdin), _) => stdin.into(),
+        (_, Some(stdout)) => stdout.into(),
+        _ => unreachable!("one end is always piped"),
+    };
+    // Only now, with the substituted command already running, may the
+    // pipe end be inherited by the commands that open the path.
+    unsafe { libc::fcntl(end.as_raw_fd(), libc::F_SETFD, 0); }
+    let path = format!("/dev/fd/{}", end.as_raw_fd());
+    shell.substitutions.push((File::from(end), child));
+    Ok(path)
+}
+
+#[cfg(not(unix))]
+fn process_substitution(_shell: &mut Shell, _command: &str, _output: bool) -> Result<String, String> {
+    Err("process substitution is not supported on this platform".into())
+}
+
+/// The command line for `( body )`: a separate `falsh -c body` with the
+/// current positional parameters, so nothing it changes (the directory,
+/// variables, options) reaches this shell. Shell variables, options and
+/// aliases go along as `--var`, `-o` and `--alias` arguments rather than
+/// through the environment, where the commands it runs would see them.
+fn subshell_args(shell: &mut Shell, body: &str) -> Result<Vec<String>, String> {
+    let exe = env::current_exe().map_err(|e| format!("subshell: {}", e))?;
+    let mut args = vec![exe.to_string_lossy().into_owned()];
+    let mut defaults = ShellOptions::default();
+    for name in ShellOptions::names() {
+        let on = *shell.options.get_mut(name).unwrap();
+        if on != *defaults.get_mut(name).unwrap() {
+            args.push(if on { "-o" } else { "+o" }.to_string());
+            args.push(name.to_string());
+        }
+    }
+    for (name, value) in &shell.vars {
+        args.push("--var".into());
+        args.push(format!("{}={}", name, value));
+    }
+    for (name, value) in &shell.aliases {
+        args.push("--alias".into());
+        args.push(format!("{}={}", name, value));
+    }
+    args.extend(["-c".to_string(), body.to_string()]);
+    args.extend(shell.positional.iter().cloned());
+    Ok(args)
+}
+
+/// Closes the process substitutions started since `mark` and waits for
+/// their commands, so the output of `>(cmd)` appears before the next prompt.
+fn finish_substitutions(shell: &mut Shell, mark: usize) {
+    for (end, mut child) in shell.substitutions.drain(mark..).collect::<Vec<_>>() {
+        drop(end);
+        child.wait().ok();
+    }
+}
+
+/// Expands words into the final argument list: braces, then variables and
+/// substitutions, then splitting and globbing.
+fn expand_words(shell: &mut Shell, words: &[Word]) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    for word in words.iter().flat_map(brace::expand) {
+        fields.extend(expand_word(shell, &word)?);
+    }
+    expand_globs(fields, &shell.options)
+}
+
+/// A zsh-style glob qualifier, one test inside the `( ... )` that may end
+/// a pattern: `*(.)` keeps regular files, `*(/)` directories.
+enum GlobQualifier {
+    /// `.`
+    File,
+    /// `/`
+    Dir,
+    /// `@`
+    Symlink,
+    /// `*`: an executable regular file.
+    Executable,
+    /// `L[kmg][+-]n`: size in bytes (or KiB, MiB, GiB, rounded up) above,
+    /// below or equal to `n`.
+    Size(std::cmp::Ordering, u64, u64),
+    /// `m[smhdwM][+-]n`: time since the last modification, in days unless
+    /// another unit is given, above, below or equal to `n`.
+    Modified(std::cmp::Ordering, u64, u64),
+}
+
+impl GlobQualifier {
+    fn holds(&self, path: &Path, now: SystemTime) -> bool {
+        let Ok(meta) = fs::metadata(path) else {
+            return matches!(self, GlobQualifier::Symlink) && fs::symlink_metadata(path).is_ok_and(|m| m.is_symlink());
+        };
+        match self {
+            GlobQualifier::File => meta.is_file(),
+            GlobQualifier::Dir => meta.is_dir(),
+            GlobQualifier::Symlink => fs::symlink_metadata(path).is_ok_and(|m| m.is_symlink()),
+            GlobQualifier::Executable => is_executable(path),
+            GlobQualifier::Size(order, unit, n) => meta.len().div_ceil(*unit).cmp(n) == *order,
+            GlobQualifier::Modified(order, unit, n) => {
+                let age = meta.modified().ok().and_then(|m| now.duration_since(m).ok()).unwrap_or_default();
+                (age.as_secs() / unit).cmp(n) == *order
+            }
+        }
+    }
+}
+
+/// Splits a trailing qualifier list like `(.)` or `(^/Lk+10)` off a glob
+/// pattern. Each test may be negated with `^`; all must hold. Anything that
+/// does not parse as qualifiers stays part of the pattern.
+fn split_qualifiers(pattern: &str) -> (&str, Vec<(bool, GlobQualifier)>) {
+    let Some(body) = pattern.strip_suffix(')') else { return (pattern, Vec::new()) };
+    let Some(open) = body.rfind('(') else { return (pattern, Vec::new()) };
+    match parse_qualifiers(&body[open + 1..]) {
+        Some(qualifiers) if !qualifiers.is_empty() => (&pattern[..open], qualifiers),
+        _ => (pattern, Vec::new()),
+    }
+}
+
+fn parse_qualifiers(spec: &str) -> Option<Vec<(bool, GlobQualifier)>> {
+    use std::cmp::Ordering;
+    let mut qualifiers = Vec::new();
+    let mut chars = spec.chars().peekable();
+    let mut negated = false;
+    while let Some(c) = chars.next() {
+        let qualifier = match c {
+            '^' => {
+                negated = !negated;
+                continue;
+            }
+            '.' => GlobQualifier::File,
+            '/' => GlobQualifier::Dir,
+            '@' => GlobQualifier::Symlink,
+            '*' => GlobQualifier::Executable,
+            'L' | 'm' => {
+                let units: &[(char, u64)] = if c == 'L' {
+                    &[('k', 1 << 10), ('m', 1 << 20), ('g', 1 << 30)]
+                } else {
+                    &[('s', 1), ('m', 60), ('h', 3600), ('d', 86400), ('w', 7 * 86400), ('M', 30 * 86400)]
+                };
+                let default = if c == 'L' { 1 } else { 86400 };
+                let unit = match units.iter().find(|(u, _)| chars.peek() == Some(u)) {
+                    Some(&(_, unit)) => {
+                        chars.next();
+                        unit
+                    }
+                    None => default,
+                };
+                let order = match chars.peek() {
+                    Some('+') => Ordering::Greater,
+                    Some('-') => Ordering::Less,
+                    _ => Ordering::Equal,
+                };
+                if order != Ordering::Equal {
+                    chars.next();
+                }
+                let mut digits = String::new();
+                while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(d);
+                }
+                let n = digits.parse().ok()?;
+                if c == 'L' { GlobQualifier::Size(order, unit, n) } else { GlobQualifier::Modified(order, unit, n) }
+            }
+            _ => return None,
+        };
+        qualifiers.push((negated, qualifier));
+        negated = false;
+    }
+    Some(qualifiers)
+}
+
+/// Whether the glob match `path` reached a hidden file or directory through
+/// a pattern component that does not start with a literal `.`, including
+/// the directories a `**` passed through. (The glob crate's own
+/// `require_literal_leading_dot` also drops `.h*` matches, so it is not
+/// used.)
+fn hides_dotfile(pattern: &str, path: &str) -> bool {
+    // The glob crate drops `./` and trailing slashes from what it returns,
+    // so neither counts as a component.
+    fn components(text: &str) -> Vec<&str> {
+        text.split('/').filter(|c| !c.is_empty() && *c != ".").collect()
+    }
+    fn hidden(pattern: &[&str], path: &[&str]) -> bool {
+        match (pattern.split_first(), path.split_first()) {
+            (None, None) => false,
+            (Some((&"**", rest)), _) => (0..=path.len()).all(|skip| {
+                path[..skip].iter().any(|dir| dir.starts_with('.')) || hidden(rest, &path[skip..])
+            }),
+            (Some((component, rest)), Some((name, names))) => {
+                (name.starts_with('.') && !component.starts_with('.')) || hidden(rest, names)
+            }
+            _ => true,
+        }
+    }
+    hidden(&components(pattern), &components(path))
+}
+
+/// Compares strings with runs of ASCII digits ordered by their value, so
+/// `file2` sorts before `file10`; ties like `01` and `1` fall back to plain
+/// byte order.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut x, mut y) = (a, b);
+    loop {
+        let (Some(cx), Some(cy)) = (x.chars().next(), y.chars().next()) else {
+            return x.len().cmp(&y.len()).then_with(|| a.cmp(b));
+        };
+        if cx.is_ascii_digit() && cy.is_ascii_digit() {
+            let dx = x.find(|c: char| !c.is_ascii_digit()).unwrap_or(x.len());
+            let dy = y.find(|c: char| !c.is_ascii_digit()).unwrap_or(y.len());
+            let (nx, ny) = (x[..dx].trim_start_matches('0'), y[..dy].trim_start_matches('0'));
+            let order = nx.len().cmp(&ny.len()).then_with(|| nx.cmp(ny));
+            if order.is_ne() {
+                return order;
+            }
+            (x, y) = (&x[dx..], &y[dy..]);
+        } else {
+            if cx != cy {
+                return cx.cmp(&cy);
+            }
+            (x, y) = (&x[cx.len_utf8()..], &y[cy.len_utf8()..]);
+        }
+    }
+}
+
+/// Turns each run of `*` into a single `*`, except, with `globstar`, a `**`
+/// that makes up a whole path component, which the glob crate matches
+/// recursively (`src/**/*.rs`).
+fn collapse_globstar(pattern: &str, globstar: bool) -> String {
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('*') {
+        let run = rest[start..].len() - rest[start..].trim_start_matches('*').len();
+        out.push_str(&rest[..start]);
+        let after = &rest[start + run..];
+        let component = (out.is_empty() || out.ends_with('/')) && (after.is_empty() || after.starts_with('/'));
+        out.push_str(if globstar && run >= 2 && component { "**" } else { "*" });
+        rest = &rest[start + run..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Expands unquoted `*`, `?` and `[...]` patterns into their matches, sorted
+/// (see `numericglobsort`). A pattern that matches nothing (or is
+/// malformed) is passed on literally, dropped with `nullglob`, or an error
+/// with `failglob`. Hidden files only match a pattern that starts with a
+/// literal `.`, unless `dotglob` is on. With `noglob` nothing is expanded.
+fn expand_globs(fields: Vec<Field>, options: &ShellOptions) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for field in fields {
+        if options.noglob || !field.glob {
+            expanded.push(field.text);
+            continue;
+        }
+        let (pattern, qualifiers) = split_qualifiers(&field.pattern);
+        let pattern = collapse_globstar(pattern, options.globstar);
+        let mut matches: Vec<String> = glob(&pattern)
+            .map(|paths| paths.filter_map(Result::ok).map(|p| p.to_string_lossy().to_string()).collect())
+            .unwrap_or_default();
+        // `.` and `..` never match, as in bash.
+        matches.retain(|path| !matches!(path.rsplit('/').next(), Some("." | "..")));
+        if !options.dotglob {
+            matches.retain(|path| !hides_dotfile(&pattern, path));
+        }
+        if !qualifiers.is_empty() {
+            let now = SystemTime::now();
+            matches.retain(|path| qualifiers.iter().all(|(negated, test)| test.holds(Path::new(path), now) != *negated));
+        }
+        if options.numericglobsort {
+            matches.sort_by(|a, b| natural_cmp(a, b));
+        } else {
+            matches.sort();
+        }
+        if matches.is_empty() {
+            if options.failglob { return Err(format!("falsh: no matches for: {}", field.text)); }
+            if !options.nullglob { expanded.push(field.text); }
+        } else {
+            expanded.extend(matches);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Changes directory, keeping `PWD` and `OLDPWD` up to date. `@` stands for
+/// the top level of the current git repository and `-` for `OLDPWD`, which
+/// is printed like in other shells. A missing target of two or more
+/// characters is checked for a typo (see `cd_spelling`).
+fn change_dir(path: &str) -> Result<(), String> {
+    let old = env::current_dir().ok();
+    let target = match path {
+        "@" => {
+            let cwd = old.clone().ok_or("cd: cannot read the current directory")?;
+            git_root(&cwd).ok_or("cd: @: not inside a git repository")?
+        }
+        "-" => PathBuf::from(env::var_os("OLDPWD").ok_or("cd: OLDPWD not set")?),
+        _ => PathBuf::from(path),
+    };
+    if let Err(e) = env::set_current_dir(&target) {
+        let spell = e.kind() == io::ErrorKind::NotFound && path.chars().count() >= 2;
+        let guess = if spell { cd_spelling(&target) } else { None };
+        match guess {
+            Some(guess) if env::var("FALSH_CDSPELL").as_deref() == Ok("1") => {
+                outln!("{}", guess.display());
+                env::set_current_dir(&guess).map_err(|e| format!("cd failed: {}", e))?;
+            }
+            Some(guess) => return Err(format!("cd: did you mean '{}'?", guess.display())),
+            None => return Err(format!("cd failed: {}", e)),
+        }
+    } else if path == "-" {
+        outln!("{}", target.display());
+    }
+    unsafe {
+        if let Some(old) = old { env::set_var("OLDPWD", old); }
+        if let Ok(new) = env::current_dir() { env::set_var("PWD", new); }
+    }
+    Ok(())
+}
+
+/// The one sibling directory whose name is a single typo away from the
+/// missing `target`, if there is exactly one.
+fn cd_spelling(target: &Path) -> Option<PathBuf> {
+    let name = target.file_name()?.to_str()?;
+    let parent = target.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut matches = fs::read_dir(parent).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|candidate| edit_distance(name, candidate) == 1);
+    let found = matches.next()?;
+    if matches.next().is_some() { return None; }
+    Some(target.with_file_name(found))
+}
+
+/// The nearest directory at or above `dir` that contains `.git`.
+fn git_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find(|d| d.join(".git").exists()).map(Path::to_path_buf)
+}
+
+fn print_working_dir() {
+    match env::current_dir() {
+        Ok(path) => outln!("{}", path.display()),
+        Err(e) => println!("pwd failed: {}", e),
+    }
+}
+
+/// XDG config directory for falsh: `$XDG_CONFIG_HOME/falsh`, else
+/// `~/.config/falsh`. `None` only when no home directory is known either.
+fn config_dir() -> Option<PathBuf> {
+    if let Some(xdg) = env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(xdg).join("falsh"));
+    }
+    dirs::home_dir().map(|home| home.join(".config").join("falsh"))
+}
+
+/// Resolves a config file, first match wins:
+///
+/// 1. `<config_dir>/<name>` (see `config_dir`)
+/// 2. the legacy dotfile `~/<legacy>`
+///
+/// If neither exists the XDG location is returned so new files land there.
+/// Without any home directory we use `./<legacy>` and warn once.
+fn config_file(name: &str, legacy: &str) -> PathBuf {
+    let xdg = config_dir().map(|dir| dir.join(name));
+    if let Some(path) = xdg.as_ref().filter(|p| p.exists()) {
+        return path.clone();
+    }
+    if let Some(path) = dirs::home_dir().map(|home| home.join(legacy)).filter(|p| p.exists()) {
+        return path;
+    }
+    xdg.unwrap_or_else(|| {
+        static WARN_ONCE: Once = Once::new();
+        WARN_ONCE.call_once(|| {
+            println!("Warning: could not find a home directory, using '.' for config files.");
+        });
+        PathBuf::from(legacy)
+    })
+}
+
+fn get_persistent_path_file() -> PathBuf {
+    config_file("path", ".falsh_path")
+}
+
+fn get_falshrc_file() -> PathBuf {
+    config_file("falshrc", ".falshrc")
+}
+
+fn get_aliases_file() -> PathBuf {
+    config_file("aliases", ".falsh_aliases")
+}
+
+fn get_history_file() -> PathBuf {
+    config_file("history", ".falsh_history")
+}
+
+fn load_persistent_paths() -> Vec<String> {
+    let file = get_persistent_path_file();
+    if !file.exists() { return vec![]; }
+    BufReader::new(File::open(file).unwrap())
+        .lines()
+        .map_while(Result::ok)
+        .collect()
+}
+
+fn save_persistent_paths(paths: &[String]) {
+    let file = get_persistent_path_file();
+    if let Some(parent) = file.parent() { fs::create_dir_all(parent).ok(); }
+    let mut f = File::create(file).unwrap();
+    for p in paths { writeln!(f, "{}", p).unwrap(); }
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(meta) = fs::metadata(path) else { return false };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.is_file() && meta.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        meta.is_file()
+    }
+}
+
+/// Expands a leading `~` and any `$VAR`/`${VAR}` in a stored path, so
+/// entries like `~/bin` can be saved as typed and still resolve.
+fn expand_path(input: &str) -> String {
+    let mut out = match expand_tilde(input, true) {
+        Some((dir, rest)) => format!("{}{}", dir, rest),
+        None => input.to_string(),
+    };
+    let mut from = 0;
+    while let Some(start) = out[from..].find('$').map(|i| from + i) {
+        let rest = &out[start + 1..];
+        let (name, len) = match rest.strip_prefix('{').and_then(|r| r.find('}').map(|end| &r[..end])) {
+            Some(name) => (name, name.len() + 2),
+            None => {
+                let name = &rest[..rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len())];
+                (name, name.len())
+            }
+        };
+        if name.is_empty() {
+            from = start + 1;
+            continue;
+        }
+        let value = env::var(name).unwrap_or_default();
+        out.replace_range(start..start + 1 + len, &value);
+        from = start + value.len();
+    }
+    out
+}
+
+/// Adds a directory to the live `PATH` and, unless `temporary`, to the
+/// saved path file. `~` and `$VAR` are expanded and the result made
+/// absolute, so the live entry works from any directory; a file stands for
+/// the directory it is in. What gets saved is the portable form: an entry
+/// written with `~` or `$VAR` is saved as typed and expanded again on every
+/// start, while any other is saved as the absolute path it resolved to,
+/// with a leading home directory written as `~` (the shell has already
+/// expanded an unquoted `~/bin` by the time it gets here).
+fn add_to_path(user_input: &str, temporary: bool) {
+    let expanded = expand_path(user_input);
+    // Canonical so `addToPath ./scripts` keeps working after a `cd`.
+    let path_to_add = match fs::canonicalize(&expanded) {
+        Ok(actual_path) => {
+            if actual_path.is_file() {
+                actual_path.parent().map(|p| p.to_path_buf()).unwrap_or(actual_path.clone())
+            } else { actual_path }
+        }
+        Err(_) => {
+            eprintln!("Warning: path {} does not exist.", user_input);
+            PathBuf::from(&expanded)
+        }
+    };
+
+    // Only worth saying when the user adds the entry, not on every start
+    // (which includes each `-c` child a subshell runs in).
+    if !temporary && path_to_add.is_dir() {
+        match fs::read_dir(&path_to_add) {
+            Ok(entries) => {
+                if !entries.filter_map(Result::ok).any(|e| is_executable(&e.path())) {
+                    eprintln!("Note: {} contains no executable files.", path_to_add.display());
+                }
+            }
+            Err(e) => eprintln!("Note: cannot read {}: {}", path_to_add.display(), e),
+        }
+    }
+
+    let add_str = path_to_add.to_string_lossy().to_string();
+
+    if !temporary {
+        let stored = if expanded == user_input { home_relative(&path_to_add) } else { user_input.to_string() };
+        let mut paths = load_persistent_paths();
+        if !paths.contains(&stored) {
+            paths.push(stored);
+            save_persistent_paths(&paths);
+        }
+    }
+
+    let mut path_env = env::var("PATH").unwrap_or_default();
+    if !path_env.split(':').any(|p| p == add_str) {
+        if !path_env.is_empty() { path_env.push(':'); }
+        path_env.push_str(&add_str);
+        unsafe {
+            env::set_var("PATH", &path_env);
+        }
+    }
+}
+
+fn prompt_line(prompt: &str) -> Option<String> {
+    disable_raw_mode().ok();
+    let mut rl = Editor::<(), rustyline::history::DefaultHistory>::new().ok()?;
+    let out = rl.readline(&format!("\r{}", prompt)).ok();
+    enable_raw_mode().ok();
+    out.map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+fn list_path() {
+    let mut paths = load_persistent_paths();
+    enable_raw_mode().unwrap();
+    let mut selected: usize = 0;
+
+    loop {
+        execute!(io::stdout(), Clear(ClearType::All), cursor::MoveTo(0, 0)).unwrap();
+        println!("Use ↑/↓ to navigate, Enter to delete/open '+', Esc to exit:\n");
+
+        for (i, path) in paths.iter().enumerate() {
+            if i == selected { println!("> {}", path); }
+            else { println!("  {}", path); }
+        }
+        let plus_idx = paths.len();
+        if selected == plus_idx { println!("> [+ Add new path]"); }
+        else { println!("  [+ Add new path]"); }
+
+        if let Event::Key(KeyEvent { code, .. }) = event::read().unwrap() {
+            match code {
+                KeyCode::Up => { if selected > 0 { selected -= 1; } else { selected = plus_idx; } }
+                KeyCode::Down => { if selected < plus_idx { selected += 1; } else { selected = 0; } }
+                KeyCode::Enter => {
+                    if selected == plus_idx {
+                        if let Some(newp) = prompt_line("Enter path to add: ") {
+                            add_to_path(&newp, false);
+                            paths = load_persistent_paths();
+                        }
+                    } else if !paths.is_empty() {
+                        paths.remove(selected);
+                        save_persistent_paths(&paths);
+                        if selected >= paths.len() && selected > 0 { selected -= 1; }
+                    }
+                }
+                KeyCode::Esc => break,
+                _ => {}
+            }
+        }
+    }
+    disable_raw_mode().unwrap();
+}
+
+/// `path` with the home directory it lies in written as `~`.
+fn home_relative(path: &Path) -> String {
+    let home = dirs::home_dir().map(|home| fs::canonicalize(&home).unwrap_or(home));
+    match home.as_ref().and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+fn load_persistent_into_env() {
+    for user_entry in load_persistent_paths() {
+        add_to_path(&user_entry, true);
+    }
+}
+
+/// ------------------- SHELL STATE -------------------
+/// Options toggled with `set -o NAME` / `set +o NAME`.
+struct ShellOptions {
+    /// Let `*`, `?` and `[...]` match a leading `.` in file names.
+    dotglob: bool,
+    errexit: bool,
+    failglob: bool,
+    /// `**` as a whole path component matches any number of directories;
+    /// off, it is just `*`. On by default.
+    globstar: bool,
+    /// A line changed by history expansion (`!!`, `!$`, ...) is put back
+    /// at the prompt to edit or confirm instead of running right away.
+    histverify: bool,
+    /// `>` refuses to truncate an existing file; `>|` still does.
+    noclobber: bool,
+    /// Print each parsed line (see `parser::dump`) instead of running it.
+    noexec: bool,
+    noglob: bool,
+    nullglob: bool,
+    /// Sort glob matches with runs of digits compared as numbers, so
+    /// `file2` comes before `file10`.
+    numericglobsort: bool,
+    pipefail: bool,
+}
+
+impl Default for ShellOptions {
+    fn default() -> Self {
+        ShellOptions {
+            dotglob: false,
+            errexit: false,
+            failglob: false,
+            globstar: true,
+            histverify: false,
+            noclobber: false,
+            noexec: false,
+            noglob: false,
+            nullglob: false,
+            numericglobsort: false,
+            pipefail: false,
+        }
+    }
+}
+
+impl ShellOptions {
+    fn names() -> &'static [&'static str] {
+        &["dotglob", "errexit", "failglob", "globstar", "histverify", "noclobber", "noexec", "noglob", "nullglob", "numericglobsort", "pipefail"]
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "errexit" => Some(&mut self.errexit),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "histverify" => Some(&mut self.histverify),
+            "noclobber" => Some(&mut self.noclobber),
+            "noexec" => Some(&mut self.noexec),
+            "noglob" => Some(&mut self.noglob),
+            "nullglob" => Some(&mut self.nullglob),
+            "numericglobsort" => Some(&mut self.numericglobsort),
+            "pipefail" => Some(&mut self.pipefail),
+            _ => None,
+        }
+    }
+}
+
+/// State that lives for the whole session and is shared by all builtins.
+#[derive(Default)]
+struct Shell {
+    options: ShellOptions,
+    last_status: i32,
+    /// Set when the last status is a failure `errexit` ignores, like the
+    /// left side of `false && cmd`.
+    errexit_exempt: bool,
+    /// Canonical paths of the files currently being sourced, innermost last.
+    sourcing: Vec<PathBuf>,
+    /// Interactive command history, oldest first.
+    history: Vec<String>,
+    /// Positional parameters of a script or `-c` command (`$1`, `$2`, ...).
+    positional: Vec<String>,
+    /// Shell variables from plain `NAME=value`; not exported to children.
+    vars: BTreeMap<String, String>,
+    /// Word lists registered with `complete`, shared with `FalshHelper`.
+    completions: Completions,
+    /// Trap commands by signal name (`INT`, `TERM`, ...) or `EXIT`.
+    traps: BTreeMap<String, String>,
+    /// Aliases by name, persisted to the aliases file.
+    aliases: BTreeMap<String, String>,
+    /// Executable names found on `PATH`, keyed by the `PATH` they came from.
+    path_commands: Option<(String, Vec<String>)>,
+    /// When the shell started, for `$SECONDS`.
+    started: StartTime,
+    /// xorshift state behind `$RANDOM`; seeded on first use.
+    rng: u64,
+    /// Open `<(cmd)` / `>(cmd)` pipe ends and their commands, oldest first.
+    substitutions: Vec<(File, Child)>,
+    /// Pipelines started with `&` that have not been reaped yet.
+    jobs: Vec<Job>,
+    /// Process id behind `$!`: the last command of the latest `&` pipeline.
+    last_background: Option<u32>,
+    /// Set for the prompt loop, where `&` prints job numbers and finished
+    /// jobs are announced.
+    interactive: bool,
+}
+
+/// A background pipeline, numbered from 1 like `[1]`.
+struct Job {
+    id: usize,
+    text: String,
+    children: Vec<Child>,
+}
+
+impl arith::Variables for Shell {
+    fn get(&mut self, name: &str) -> Option<String> {
+        self.lookup_var(name)
+    }
+
+    fn set(&mut self, name: &str, value: arith::Number) {
+        self.set_var(name, &value.to_string());
+    }
+}
+
+/// An `Instant` that defaults to "now", so `Shell::default()` stamps its
+/// own start time.
+struct StartTime(Instant);
+
+impl Default for StartTime {
+    fn default() -> Self {
+        StartTime(Instant::now())
+    }
+}
+
+impl Shell {
+    /// Whether `errexit` should stop the shell after a command returned `status`.
+    fn errexit_stops(&self, status: i32) -> bool {
+        self.options.errexit && status != 0 && !self.errexit_exempt
+    }
+
+    /// Value of a variable, with the dynamic `$RANDOM` and `$SECONDS`
+    /// taking precedence over the environment.
+    fn lookup_var(&mut self, name: &str) -> Option<String> {
+        match name {
+            "RANDOM" => Some(self.next_random().to_string()),
+            "SECONDS" => Some(self.started.0.elapsed().as_secs().to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            "0" => Some("falsh".to_string()),
+            "$" => Some(std::process::id().to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "?" => Some(self.last_status.to_string()),
+            _ => match name.parse::<usize>() {
+                Ok(n) => n.checked_sub(1).and_then(|n| self.positional.get(n)).cloned(),
+                Err(_) => self.vars.get(name).cloned().or_else(|| env::var(name).ok()),
+            },
+        }
+    }
+
+    /// Assigns a variable: exported ones stay in the environment, anything
+    /// else becomes a shell variable that children never see.
+    fn set_var(&mut self, name: &str, value: &str) {
+        if env::var_os(name).is_some() {
+            unsafe { env::set_var(name, value); }
+        } else {
+            self.vars.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Next pseudo-random number in 0..=32767.
+    fn next_random(&mut self) -> u32 {
+        if self.rng == 0 {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+            self.rng = (nanos ^ u64::from(std::process::id())) | 1;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 33) as u32 % 32768
+    }
+
+    /// Names of the executables on `PATH`, rescanned only when it changes.
+    fn path_commands(&mut self) -> &[String] {
+        let path = env::var("PATH").unwrap_or_default();
+        if self.path_commands.as_ref().is_none_or(|(cached, _)| *cached != path) {
+            let mut names: Vec<String> = env::split_paths(&path)
+                .filter_map(|dir| fs::read_dir(dir).ok())
+                .flat_map(|entries| entries.filter_map(Result::ok))
+                .filter(|entry| is_executable(&entry.path()))
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names.dedup();
+            self.path_commands = Some((path, names));
+        }
+        &self.path_commands.as_ref().unwrap().1
+    }
+}
+
+/// Runs one line and records its status, printing any error. Traps for
+/// signals that arrived meanwhile run afterwards.
+fn run_line(shell: &mut Shell, input: &str) -> Result<(), String> {
+    emit_event(&format!("{{\"type\":\"start\",\"cmd\":{}}}", json_string(input)));
+    let result = execute_line(shell, input);
+    shell.last_status = match result {
+        Ok(status) => status,
+        Err(_) => 1,
+    };
+    emit_event(&format!(
+        "{{\"type\":\"exec\",\"cmd\":{},\"status\":{}}}",
+        json_string(input),
+        shell.last_status
+    ));
+    run_pending_traps(shell);
+    result.map(|_| ())
+}
+
+/// Writes one JSON event line to the descriptor named by `FALSH_EVENT_FD`,
+/// if set. Tools watching the shell get a `start` event before each command
+/// line and an `exec` event with its status afterwards.
+#[cfg(unix)]
+fn emit_event(json: &str) {
+    let Some(fd) = env::var("FALSH_EVENT_FD").ok().and_then(|fd| fd.trim().parse::<i32>().ok()) else { return };
+    let line = format!("{}\n", json);
+    let mut bytes = line.as_bytes();
+    while !bytes.is_empty() {
+        let written = unsafe { libc::write(fd, bytes.as_ptr().cast(), bytes.len()) };
+        if written <= 0 { return; }
+        bytes = &bytes[written as usize..];
+    }
+}
+
+#[cfg(not(unix))]
+fn emit_event(_json: &str) {}
+
+/// Quotes `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// ------------------- TRAPS -------------------
+/// Signals `trap` accepts, by name without the `SIG` prefix.
+#[cfg(unix)]
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("WINCH", libc::SIGWINCH),
+];
+#[cfg(not(unix))]
+const SIGNALS: &[(&str, i32)] = &[];
+
+/// Bit `n` is set when signal `n` arrived and its trap has not run yet.
+static PENDING_SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(unix)]
+extern "C" fn note_signal(sig: libc::c_int) {
+    PENDING_SIGNALS.fetch_or(1 << sig, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn ignore_signal(_sig: libc::c_int) {}
+
+/// Normalizes `INT`, `SIGINT`, `int`, `2` or `EXIT`/`0` to a trap name.
+fn trap_name(spec: &str) -> Option<&'static str> {
+    let upper = spec.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    if name == "EXIT" || name == "0" {
+        return Some("EXIT");
+    }
+    SIGNALS.iter()
+        .find(|(n, num)| *n == name || num.to_string() == name)
+        .map(|(n, _)| *n)
+}
+
+fn signal_number(name: &str) -> Option<i32> {
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|(_, num)| *num)
+}
+
+/// What the shell does on a signal: run a trap, ignore it, or the default.
+#[cfg(unix)]
+fn set_signal_action(sig: i32, command: Option<&str>) {
+    let handler = match command {
+        Some("") => libc::SIG_IGN,
+        Some(_) => note_signal as *const () as libc::sighandler_t,
+        // A broken pipe must not kill the shell, only the commands it runs.
+        None if sig == libc::SIGPIPE => ignore_signal as *const () as libc::sighandler_t,
+        None => libc::SIG_DFL,
+    };
+    unsafe { libc::signal(sig, handler); }
+}
+
+#[cfg(not(unix))]
+fn set_signal_action(_sig: i32, _command: Option<&str>) {}
+
+/// `trap 'cmd' SIG...` sets a trap, `trap - SIG...` resets it, `trap -l`
+/// lists signal names and bare `trap` lists the traps that are set.
+fn trap_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    match args {
+        [] => {
+            for (name, command) in &shell.traps {
+                outln!("trap -- '{}' {}", command.replace('\'', "'\"'\"'"), name);
+            }
+            Ok(0)
+        }
+        [flag] if flag == "-l" => {
+            let listing: Vec<String> = SIGNALS.iter().map(|(name, num)| format!("{}) SIG{}", num, name)).collect();
+            outln!("{}", listing.join(" "));
+            Ok(0)
+        }
+        [_] => Err("trap: usage: trap [-l] [[command|-] signal...]".into()),
+        [command, specs @ ..] => {
+            let mut status = 0;
+            for spec in specs {
+                let Some(name) = trap_name(spec) else {
+                    println!("trap: {}: invalid signal specification", spec);
+                    status = 1;
+                    continue;
+                };
+                let command = (command != "-").then_some(command.as_str());
+                if let Some(sig) = signal_number(name) {
+                    set_signal_action(sig, command);
+                }
+                match command {
+                    Some(command) => { shell.traps.insert(name.to_string(), command.to_string()); }
+                    None => { shell.traps.remove(name); }
+                }
+            }
+            Ok(status)
+        }
+    }
+}
+
+/// Runs the traps of signals caught since the last call, keeping `$?`.
+fn run_pending_traps(shell: &mut Shell) {
+    let pending = PENDING_SIGNALS.swap(0, Ordering::SeqCst);
+    if pending == 0 { return; }
+    for (name, sig) in SIGNALS {
+        if pending & (1 << sig) != 0 {
+            run_trap(shell, name);
+        }
+    }
+}
+
+/// Runs the command trapped for `name`, if any, keeping `$?`.
+fn run_trap(shell: &mut Shell, name: &str) {
+    let Some(command) = shell.traps.get(name).filter(|c| !c.is_empty()).cloned() else { return };
+    let status = shell.last_status;
+    if let Err(e) = execute_line(shell, &command) {
+        println!("trap {}: {}", name, e);
+    }
+    shell.last_status = status;
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(sig) = status.signal() { return 128 + sig; }
+    }
+    status.code().unwrap_or(1)
+}
+
+fn print_options(options: &mut ShellOptions) {
+    for name in ShellOptions::names() {
+        let on = *options.get_mut(name).unwrap();
+        outln!("{:<12}{}", name, if on { "on" } else { "off" });
+    }
+}
+
+fn set_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    if args.is_empty() {
+        print_options(&mut shell.options);
+        return Ok(0);
+    }
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "-o" | "+o" => match iter.next() {
+                Some(name) => {
+                    let opt = shell.options.get_mut(name)
+                        .ok_or_else(|| format!("set: {}: invalid option name", name))?;
+                    *opt = flag == "-o";
+                }
+                None => print_options(&mut shell.options),
+            },
+            "-e" | "+e" => shell.options.errexit = flag == "-e",
+            "-C" | "+C" => shell.options.noclobber = flag == "-C",
+            "-f" | "+f" => shell.options.noglob = flag == "-f",
+            "-n" | "+n" => shell.options.noexec = flag == "-n",
+            _ => return Err(format!("set: {}: invalid option", flag)),
+        }
+    }
+    Ok(0)
+}
+
+/// Quotes a word so the parser reads it back unchanged. Glob characters
+/// stay bare so the re-parsed command still expands them.
+fn quote_word(word: &str) -> String {
+    let safe = |c: char| c.is_alphanumeric() || "-_./=:,+@%^*?[]~".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\"'\"'"))
+    }
+}
+
+/// What a redirected descriptor ends up pointing at.
+enum RedirectTarget {
+    File(File),
+    /// `n>&m` / `n<&m`: a copy of descriptor `m`.
+    Dup(i32),
+    /// `n>&-`
+    Close,
+}
+
+/// A command's redirections, applied in the order written so that
+/// `> file 2>&1` and `2>&1 > file` differ as in sh.
+#[derive(Default)]
+struct Redirections {
+    actions: Vec<(i32, RedirectTarget)>,
+}
+
+impl Redirections {
+    /// Whether `fd` is open once the actions so far are applied. Checked
+    /// before `n>&m` so a child never copies a descriptor it should not see.
+    fn is_open(&self, fd: i32) -> bool {
+        match self.actions.iter().rev().find(|(dst, _)| *dst == fd) {
+            Some((_, RedirectTarget::Close)) => false,
+            Some(_) => true,
+            None => fd_is_open(fd),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn fd_is_open(fd: i32) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 }
+}
+
+#[cfg(not(unix))]
+fn fd_is_open(fd: i32) -> bool {
+    (0..=2).contains(&fd)
+}
+
+/// Opens the files a command's redirections name.
+fn open_redirections(shell: &mut Shell, command: &SimpleCommand) -> Result<Redirections, String> {
+    let mut redirs = Redirections::default();
+    for (idx, redirect) in command.redirects.iter().enumerate() {
+        let target = expand_single(shell, &redirect.target)?;
+        let open = |result: io::Result<File>| result.map(RedirectTarget::File).map_err(|e| format!("{}: {}", target, e));
+        let action = match redirect.op {
+            RedirectOp::Read => open(File::open(&target))?,
+            RedirectOp::ReadWrite => open(fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&target))?,
+            RedirectOp::Write if shell.options.noclobber => open(create_no_clobber(&target))?,
+            RedirectOp::Write | RedirectOp::Clobber => open(File::create(&target))?,
+            RedirectOp::Append => open(fs::OpenOptions::new().append(true).create(true).open(&target))?,
+            RedirectOp::HereDoc => RedirectTarget::File(text_pipe(target)?),
+            RedirectOp::HereString => RedirectTarget::File(text_pipe(format!("{}\n", target))?),
+            RedirectOp::DupIn | RedirectOp::DupOut => match target.as_str() {
+                "-" => RedirectTarget::Close,
+                fd => match fd.parse() {
+                    Ok(src) if redirs.is_open(src) => RedirectTarget::Dup(src),
+                    _ => return Err(format!("{}: bad file descriptor", fd)),
+                },
+            },
+        };
+        redirs.actions.push((redirect.fd, action));
+        // `cmd > my file.txt` writes to `my` and passes `file.txt` on.
+        let next_is_redirect = command.redirects.get(idx + 1).is_some_and(|r| r.position == redirect.position);
+        let names_file = matches!(redirect.op, RedirectOp::Read | RedirectOp::ReadWrite | RedirectOp::Write | RedirectOp::Clobber | RedirectOp::Append);
+        if let Some(next) = command.words.get(redirect.position).filter(|_| names_file && !next_is_redirect) {
+            eprintln!(
+                "Warning: '{}' after the redirection target is passed as an argument; quote the filename if it contains spaces.",
+                next.unquoted()
+            );
+        }
+    }
+    Ok(redirs)
+}
+
+/// Opens `path` for `>` under `noclobber`: a new file is created, but an
+/// existing regular file is left alone. Devices like `/dev/null` and
+/// named pipes are still opened, as in bash.
+fn create_no_clobber(path: &str) -> io::Result<File> {
+    match fs::OpenOptions::new().write(true).create_new(true).open(path) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            if fs::metadata(path)?.is_file() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "cannot overwrite existing file"));
+            }
+            fs::OpenOptions::new().write(true).open(path)
+        }
+        result => result,
+    }
+}
+
+/// A readable pipe that yields `text`, for here-documents and here-strings. A thread feeds
+/// it so a body larger than the pipe buffer cannot stall the shell.
+#[cfg(unix)]
+fn text_pipe(text: String) -> Result<File, String> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(format!("here-document: {}", io::Error::last_os_error()));
+    }
+    // Children must not inherit the write end, or they never see EOF.
+    unsafe {
+        libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    let reader = unsafe { File::from_raw_fd(fds[0]) };
+    let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+    std::thread::spawn(move || writer.write_all(text.as_bytes()));
+    Ok(reader)
+}
+
+#[cfg(not(unix))]
+fn text_pipe(_text: String) -> Result<File, String> {
+    Err("here-documents are not supported on this platform".into())
+}
+
+/// Points the shell's own descriptors at a builtin's redirections for as
+/// long as it runs, returning what `restore_fds` needs to undo it.
+#[cfg(unix)]
+fn redirect_shell_fds(redirs: Redirections) -> Result<Vec<(i32, i32)>, String> {
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+    io::stdout().flush().ok();
+    let mut saved = Vec::new();
+    for (fd, target) in redirs.actions {
+        // Copies live above the descriptors scripts usually pick.
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+        saved.push((fd, copy));
+        let ok = match target {
+            // The file already sits on the descriptor; keep it there.
+            RedirectTarget::File(file) if file.as_raw_fd() == fd => {
+                let _ = file.into_raw_fd();
+                true
+            }
+            RedirectTarget::File(file) => unsafe { libc::dup2(file.as_raw_fd(), fd) >= 0 },
+            RedirectTarget::Dup(src) => unsafe { libc::dup2(src, fd) >= 0 },
+            RedirectTarget::Close => unsafe { libc::close(fd) >= 0 || copy < 0 },
+        };
+        if !ok {
+            let err = io::Error::last_os_error();
+            restore_fds(saved);
+            return Err(format!("redirection: {}", err));
+        }
+    }
+    Ok(saved)
+}
+
+/// Puts back the descriptors `redirect_shell_fds` replaced, closing those
+/// that were not open before.
+#[cfg(unix)]
+fn restore_fds(saved: Vec<(i32, i32)>) {
+    io::stdout().flush().ok();
+    for (fd, copy) in saved.into_iter().rev() {
+        unsafe {
+            if copy >= 0 {
+                libc::dup2(copy, fd);
+                libc::close(copy);
+            } else {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Keeps redirections applied by `redirect_shell_fds` for good, as a bare
+/// `exec > file` does, dropping the saved originals.
+#[cfg(unix)]
+fn keep_fds(saved: Vec<(i32, i32)>) {
+    for (_, copy) in saved {
+        if copy >= 0 { unsafe { libc::close(copy); } }
+    }
+}
+
+#[cfg(not(unix))]
+fn redirect_shell_fds(redirs: Redirections) -> Result<Vec<(i32, i32)>, String> {
+    if redirs.actions.is_empty() { Ok(Vec::new()) } else { Err("redirecting builtins is not supported on this platform".into()) }
+}
+
+#[cfg(not(unix))]
+fn restore_fds(_saved: Vec<(i32, i32)>) {}
+
+#[cfg(not(unix))]
+fn keep_fds(_saved: Vec<(i32, i32)>) {}
+
+/// Applies the redirections in the child, after its pipes are in place.
+/// `redirs` must stay alive until the command has been spawned.
+#[cfg(unix)]
+fn wire_fds(cmd: &mut Command, redirs: &Redirections) -> Result<(), String> {
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let actions: Vec<(i32, Option<i32>)> = redirs.actions.iter()
+        .map(|(fd, target)| (*fd, match target {
+            RedirectTarget::File(file) => Some(file.as_raw_fd()),
+            RedirectTarget::Dup(src) => Some(*src),
+            RedirectTarget::Close => None,
+        }))
+        .collect();
+    unsafe {
+        cmd.pre_exec(move || {
+            for &(dst, src) in &actions {
+                let ok = match src {
+                    // dup2 onto itself keeps close-on-exec set, so clear it by hand.
+                    Some(src) if src == dst => libc::fcntl(src, libc::F_SETFD, 0) >= 0,
+                    Some(src) => libc::dup2(src, dst) >= 0,
+                    None => {
+                        libc::close(dst);
+                        true
+                    }
+                };
+                if !ok { return Err(io::Error::last_os_error()); }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Without `pre_exec`, only files on the standard descriptors can be wired.
+#[cfg(not(unix))]
+fn wire_fds(cmd: &mut Command, redirs: &Redirections) -> Result<(), String> {
+    for (fd, target) in &redirs.actions {
+        let RedirectTarget::File(file) = target else {
+            return Err("descriptor duplication is not supported on this platform".into());
+        };
+        let file = file.try_clone().map_err(|e| e.to_string())?;
+        match fd {
+            0 => { cmd.stdin(file); }
+            1 => { cmd.stdout(file); }
+            2 => { cmd.stderr(file); }
+            _ => return Err(format!("redirecting descriptor {} is not supported on this platform", fd)),
+        }
+    }
+    Ok(())
+}
+
+/// `exec cmd...` replaces falsh with `cmd`. With only redirections, they
+/// stay applied to the shell's own descriptors for the rest of the session
+/// (see `spawn_pipeline`).
+fn exec_builtin(args: &[String]) -> Result<i32, String> {
+    if args.is_empty() {
+        return Ok(0);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Only returns if the exec failed.
+        let err = Command::new(&args[0]).args(&args[1..]).exec();
+        Err(format!("exec: {}: {}", args[0], err))
+    }
+    #[cfg(not(unix))]
+    {
+        Err("exec: not supported on this platform".into())
+    }
+}
+
+fn repeat_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    let (count, command) = match args.split_first() {
+        Some((count, command)) if !command.is_empty() => (count, command),
+        _ => return Err("repeat: usage: repeat <count> <command...>".into()),
+    };
+    let count: u64 = match count.parse() {
+        Ok(n) if n > 0 => n,
+        _ => return Err(format!("repeat: {}: count must be a positive integer", count)),
+    };
+    let line = command.iter().map(|w| quote_word(w)).collect::<Vec<_>>().join(" ");
+    let mut status = 0;
+    for _ in 0..count {
+        status = execute_line(shell, &line)?;
+        if shell.errexit_stops(status) { break; }
+    }
+    Ok(status)
+}
+
+/// ------------------- ALIASES -------------------
+/// Parses a line, replacing the first word of each command with its alias.
+/// The replacement is parsed again, so an alias may expand to another alias
+/// or to whole pipelines, but a word that came from expanding an alias is
+/// never expanded as that alias again, even in a later command of the
+/// value (`alias a='echo x; a'`). As in sh, an alias whose value ends in a
+/// blank (`alias sudo='sudo '`) has the word after it checked for an alias
+/// too.
+fn parse_line(shell: &Shell, input: &str) -> Result<List, String> {
+    let mut line = input.to_string();
+    let mut command = 0;
+    // Where the word to check starts, past the first word; and where the
+    // last value ending in a blank ends.
+    let mut from = None;
+    let mut blank_end: Option<usize> = None;
+    // Each alias expanded so far and the part of `line` its value now
+    // covers, including whatever that value expanded to in turn.
+    let mut expanded: Vec<(String, std::ops::Range<usize>)> = Vec::new();
+    loop {
+        let list = parser::parse(&line)?;
+        let Some(current) = list.commands().nth(command) else { return Ok(list) };
+        let word = match from {
+            None => current.words.first(),
+            Some(from) => current.words.iter().find(|word| word.start >= from),
+        };
+        let alias = word
+            .and_then(|word| Some((word, word.literal()?)))
+            .filter(|(word, name)| !expanded.iter().any(|(seen, span)| seen == name && span.contains(&word.start)))
+            .and_then(|(word, name)| Some((word.start..word.end, name.to_string(), shell.aliases.get(name)?)));
+        match alias {
+            Some((span, name, value)) => {
+                if expanded.len() >= MAX_ALIAS_EXPANSIONS {
+                    return Err("alias: too many expansions in one line".into());
+                }
+                let end = span.start + value.len();
+                let grown = value.len() as isize - span.len() as isize;
+                blank_end = match value.ends_with(char::is_whitespace) {
+                    true => Some(end),
+                    false => blank_end.map(|blank| blank.saturating_add_signed(grown)),
+                };
+                for (_, outer) in &mut expanded {
+                    if outer.start >= span.end {
+                        outer.start = outer.start.saturating_add_signed(grown);
+                    }
+                    if outer.end >= span.end {
+                        outer.end = outer.end.saturating_add_signed(grown);
+                    }
+                }
+                line.replace_range(span.clone(), value);
+                expanded.push((name, span.start..end));
+            }
+            None => match blank_end.take() {
+                Some(end) => from = Some(end),
+                None => {
+                    command += 1;
+                    from = None;
+                }
+            },
+        }
+    }
+}
+
+/// How many aliases one line may expand, a backstop against values that
+/// keep producing new words to expand.
+const MAX_ALIAS_EXPANSIONS: usize = 100;
+
+/// Defines `name=value`, or prints the alias when there is no `=`. A name
+/// must be one plain word, since only those are looked up as aliases.
+fn define_alias(shell: &mut Shell, word: &str) -> Result<(), String> {
+    let invalid = |name: &str| name.is_empty() || name.contains(|c: char| c.is_whitespace() || "/|&;<>()$`\\'\"".contains(c));
+    match word.split_once('=') {
+        Some((name, _)) if invalid(name) => {
+            Err(format!("alias: '{}': invalid alias name", word))
+        }
+        Some((name, value)) => {
+            shell.aliases.insert(name.to_string(), value.to_string());
+            Ok(())
+        }
+        None => match shell.aliases.get(word) {
+            Some(value) => {
+                outln!("{}", alias_line(word, value));
+                Ok(())
+            }
+            None => Err(format!("alias: {}: not found", word)),
+        },
+    }
+}
+
+/// An alias as `alias name='value'`, which `load_aliases` and `.falshrc`
+/// both read back. Always quoted, so a `#` in the value is never a comment.
+fn alias_line(name: &str, value: &str) -> String {
+    format!("alias {}='{}'", name, value.replace('\'', "'\"'\"'"))
+}
+
+/// `alias name=value...` defines aliases and `alias name...` prints them;
+/// bare `alias` or `alias -p` lists them all.
+fn alias_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    let args = args.strip_prefix(&["-p".to_string()]).unwrap_or(args);
+    if args.is_empty() {
+        let listing: String = shell.aliases.iter().map(|(name, value)| alias_line(name, value) + "\n").collect();
+        if !listing.is_empty() { page_output(&listing); }
+        return Ok(0);
+    }
+    let mut result = Ok(0);
+    for word in args {
+        if let Err(e) = define_alias(shell, word) {
+            println!("{}", e);
+            result = Ok(1);
+        }
+    }
+    save_aliases(shell);
+    result
+}
+
+fn unalias_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    if args.is_empty() {
+        return Err("unalias: usage: unalias [-a] name...".into());
+    }
+    if args[0] == "-a" {
+        shell.aliases.clear();
+        save_aliases(shell);
+        return Ok(0);
+    }
+    let mut status = 0;
+    for name in args {
+        if shell.aliases.remove(name).is_none() {
+            println!("unalias: {}: not found", name);
+            status = 1;
+        }
+    }
+    save_aliases(shell);
+    Ok(status)
+}
+
+fn save_aliases(shell: &Shell) {
+    let file = get_aliases_file();
+    if let Some(parent) = file.parent() { fs::create_dir_all(parent).ok(); }
+    let contents: String = shell.aliases.iter().map(|(name, value)| alias_line(name, value) + "\n").collect();
+    if let Err(e) = fs::write(&file, contents) {
+        println!("Failed to save aliases to {}: {}", file.display(), e);
+    }
+}
+
+/// Reads `alias name='value'` lines from the aliases file; anything else
+/// in it is ignored. The parser drops comments and honors quotes, so
+/// `alias note='echo # todo'` keeps its `#`.
+fn load_aliases(shell: &mut Shell) {
+    let file = get_aliases_file();
+    let Ok(contents) = fs::read_to_string(&file) else { return };
+    for line in contents.lines() {
+        let Ok(list) = parser::parse(line.trim()) else { continue };
+        let Some(command) = list.items.first().and_then(|(_, p)| p.commands.first()) else { continue };
+        let words: Vec<String> = command.words.iter().map(Word::unquoted).collect();
+        if words.first().map(String::as_str) != Some("alias") { continue; }
+        for word in &words[1..] {
+            define_alias(shell, word).ok();
+        }
+    }
+}
+
+/// `complete -W "words" cmd...` registers a word list for Tab completion of
+/// `cmd`'s arguments. Without arguments, lists the registered ones.
+fn complete_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    match args {
+        [] => {
+            for (cmd, words) in shell.completions.borrow().iter() {
+                outln!("complete -W '{}' {}", words.join(" "), cmd);
+            }
+            Ok(0)
+        }
+        [flag, words, cmds @ ..] if flag == "-W" && !cmds.is_empty() => {
+            let words: Vec<String> = words.split_whitespace().map(String::from).collect();
+            let mut registry = shell.completions.borrow_mut();
+            for cmd in cmds {
+                registry.insert(cmd.clone(), words.clone());
+            }
+            Ok(0)
+        }
+        _ => Err("complete: usage: complete -W \"words\" command...".into()),
+    }
+}
+
+/// `shift [n]` drops the first `n` positional parameters (default 1).
+fn shift_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    let count = match args.first() {
+        Some(n) => n.parse::<usize>().map_err(|_| format!("shift: {}: numeric argument required", n))?,
+        None => 1,
+    };
+    if count > shell.positional.len() {
+        return Err(format!("shift: {}: shift count out of range", count));
+    }
+    shell.positional.drain(..count);
+    Ok(0)
+}
+
+/// `please` re-runs the previous command with `sudo`, like `sudo !!`.
+fn please_builtin(shell: &mut Shell) -> Result<i32, String> {
+    let previous = shell.history.iter().rev()
+        .find(|line| line.split_whitespace().next() != Some("please"))
+        .cloned();
+    let Some(previous) = previous else {
+        println!("please: no previous command to run");
+        return Ok(1);
+    };
+    if previous.split_whitespace().next() == Some("sudo") {
+        println!("please: '{}' already runs with sudo", previous);
+        return Ok(1);
+    }
+    let line = format!("sudo {}", previous);
+    println!("{}", line);
+    execute_line(shell, &line)
+}
+
+/// Expands history references in a typed line: `!!` (the previous line),
+/// `!n` (line n of the history), `!-n` (n lines back), `!prefix` (the
+/// latest line starting with it), and `!$`, `!^` and `!*` (the last, first
+/// and all arguments of the previous line). A `!` is left alone inside
+/// single quotes, after a backslash, `$` or `[`, and when followed by a
+/// space, `=`, `(` or nothing.
+fn expand_history(history: &[String], line: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut prev = None;
+    let mut pos = 0;
+    while let Some(c) = line[pos..].chars().next() {
+        pos += c.len_utf8();
+        match c {
+            '\\' if !in_single => {
+                out.push(c);
+                if let Some(escaped) = line[pos..].chars().next() {
+                    out.push(escaped);
+                    pos += escaped.len_utf8();
+                }
+                prev = None;
+                continue;
+            }
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '!' if !in_single && !matches!(prev, Some('$' | '[')) => {
+                if let Some((text, len)) = history_event(history, &line[pos..])? {
+                    out.push_str(&text);
+                    pos += len;
+                    prev = text.chars().last();
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    Ok(out)
+}
+
+/// The text a history reference stands for and the length of `rest` (the
+/// text after its `!`) it takes up, or `None` when `!` starts no reference.
+fn history_event(history: &[String], rest: &str) -> Result<Option<(String, usize)>, String> {
+    let not_found = |event: &str| format!("!{}: event not found", event);
+    let previous = || history.last().ok_or_else(|| not_found(&rest[..1]));
+    let digits = |from: usize| rest[from..].len() - rest[from..].trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let Some(first) = rest.chars().next() else { return Ok(None) };
+    let (text, len) = match first {
+        '!' => (previous()?.clone(), 1),
+        '$' | '^' | '*' => {
+            let words = history_words(previous()?);
+            let text = match first {
+                '$' => words.last().copied().unwrap_or_default().to_string(),
+                '^' => words.get(1).copied().unwrap_or_default().to_string(),
+                _ => words.get(1..).unwrap_or_default().join(" "),
+            };
+            (text, 1)
+        }
+        '-' if digits(1) > 0 => {
+            let len = 1 + digits(1);
+            let back: usize = rest[1..len].parse().map_err(|_| not_found(&rest[..len]))?;
+            let line = history.len().checked_sub(back).and_then(|idx| history.get(idx));
+            (line.ok_or_else(|| not_found(&rest[..len]))?.clone(), len)
+        }
+        '0'..='9' => {
+            let len = digits(0);
+            let number: usize = rest[..len].parse().map_err(|_| not_found(&rest[..len]))?;
+            let line = number.checked_sub(1).and_then(|idx| history.get(idx));
+            (line.ok_or_else(|| not_found(&rest[..len]))?.clone(), len)
+        }
+        c if c.is_whitespace() || "=(\"'".contains(c) => return Ok(None),
+        _ => {
+            let len = rest.find(|c: char| c.is_whitespace() || "|&;<>()\"'".contains(c)).unwrap_or(rest.len());
+            let prefix = &rest[..len];
+            let line = history.iter().rev().find(|line| line.starts_with(prefix));
+            (line.ok_or_else(|| not_found(prefix))?.clone(), len)
+        }
+    };
+    Ok(Some((text, len)))
+}
+
+/// The words of a history line, split on whitespace outside quotes, for
+/// `!$`, `!^` and `!*`.
+fn history_words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote != Some('\'') => escaped = true,
+            '\'' | '"' if quote == Some(c) => quote = None,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            c if c.is_whitespace() && quote.is_none() => {
+                if let Some(start) = start.take() {
+                    words.push(&line[start..idx]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(idx);
+    }
+    words.extend(start.map(|start| &line[start..]));
+    words
+}
+
+/// ------------------- UPDATED EXECUTE_LINE -------------------
+/// Parses a line and runs it with `run_list`.
+fn execute_line(shell: &mut Shell, input: &str) -> Result<i32, String> {
+    if input.is_empty() { return Ok(0); }
+    let list = parse_line(shell, input)?;
+    // Under `noexec`, a lone `set` still runs so `set +n` can end it.
+    let lone_set = matches!(list.items.as_slice(), [(_, pipeline)] if pipeline.commands.len() == 1
+        && pipeline.commands[0].words.first().and_then(|word| word.literal()) == Some("set"));
+    if shell.options.noexec && !lone_set {
+        print!("{}", parser::dump(&list));
+        return Ok(0);
+    }
+    run_list(shell, &list)
+}
+
+/// Runs the pipelines of a list in turn, skipping those after `&&` or `||`
+/// that the previous status rules out, and returns the status of the last
+/// one run. An error in an earlier pipeline is reported and counts as
+/// status 1 without stopping the rest. `errexit` only stops the list on a
+/// failure that is not followed by `&&` or `||`.
+fn run_list(shell: &mut Shell, list: &List) -> Result<i32, String> {
+    shell.errexit_exempt = false;
+    let mut status = 0;
+    for (i, (connector, pipeline)) in list.items.iter().enumerate() {
+        let runs = match connector {
+            Connector::Seq => true,
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        };
+        if !runs { continue; }
+        let next = list.items.get(i + 1).map(|(connector, _)| *connector);
+        status = match run_pipeline(shell, pipeline) {
+            Ok(status) => status,
+            Err(e) if next.is_some() => {
+                println!("{}", e);
+                1
+            }
+            Err(e) => return Err(e),
+        };
+        shell.last_status = status;
+        shell.errexit_exempt = next.is_some_and(|c| c != Connector::Seq);
+        if shell.errexit_stops(status) { break; }
+    }
+    Ok(status)
+}
+
+/// Runs a pipeline and returns its exit status: the last stage's status, or
+/// with `pipefail` the rightmost non-zero one. All stages run concurrently.
+/// A background pipeline is left running as a job and gives status 0.
+fn run_pipeline(shell: &mut Shell, pipeline: &Pipeline) -> Result<i32, String> {
+    // Background jobs read nothing rather than fight the prompt for input.
+    let mut previous_output: Option<File> = if pipeline.background { Some(null_input()?) } else { None };
+    let mut children: Vec<(usize, Child)> = Vec::new();
+    let mut statuses = vec![0; pipeline.commands.len()];
+    let substitutions = shell.substitutions.len();
+
+    if !shell.interactive {
+        reap_jobs(shell);
+    }
+    let spawned = spawn_pipeline(shell, pipeline, &mut previous_output, &mut children, &mut statuses);
+    if pipeline.background {
+        finish_substitutions(shell, substitutions);
+        spawned?;
+        start_job(shell, pipeline, children.into_iter().map(|(_, child)| child).collect());
+        return Ok(0);
+    }
+
+    for (i, mut child) in children {
+        statuses[i] = child.wait().map(exit_code).unwrap_or(1);
+    }
+    finish_substitutions(shell, substitutions);
+    spawned?;
+
+    let status = if shell.options.pipefail {
+        statuses.iter().rev().copied().find(|&s| s != 0).unwrap_or(0)
+    } else {
+        statuses.last().copied().unwrap_or(0)
+    };
+    Ok(status)
+}
+
+/// Records the children of a background pipeline as a job, announcing its
+/// number and last process id at the prompt as `[1] 12345`.
+fn start_job(shell: &mut Shell, pipeline: &Pipeline, children: Vec<Child>) {
+    let Some(last) = children.last() else { return };
+    shell.last_background = Some(last.id());
+    let id = (1..).find(|id| !shell.jobs.iter().any(|job| job.id == *id)).unwrap_or(1);
+    if shell.interactive {
+        eprintln!("[{}] {}", id, last.id());
+    }
+    let text = pipeline.commands.iter().map(|command| command.text.as_str()).collect::<Vec<_>>().join(" | ");
+    shell.jobs.push(Job { id, text, children });
+}
+
+/// Removes the jobs whose processes have all exited, so none is left a
+/// zombie, and returns them with the status of their last process.
+fn reap_jobs(shell: &mut Shell) -> Vec<(Job, i32)> {
+    let mut finished = Vec::new();
+    let mut idx = 0;
+    while idx < shell.jobs.len() {
+        let job = &mut shell.jobs[idx];
+        let mut status = Some(0);
+        for child in &mut job.children {
+            match child.try_wait() {
+                Ok(Some(exit)) => status = status.map(|_| exit_code(exit)),
+                Ok(None) => status = None,
+                Err(_) => status = status.map(|_| 1),
+            }
+        }
+        match status {
+            Some(status) => finished.push((shell.jobs.remove(idx), status)),
+            None => idx += 1,
+        }
+    }
+    finished
+}
+
+/// Announces background jobs that finished since the last prompt.
+fn report_jobs(shell: &mut Shell) {
+    for (job, status) in reap_jobs(shell) {
+        let state = if status == 0 { "Done".to_string() } else { format!("Exit {}", status) };
+        eprintln!("[{}]  {:<8}{}", job.id, state, job.text);
+    }
+}
+
+/// Levenshtein distance between two strings, counting a swap of adjacent
+/// characters (`gti` -> `git`) as a single edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() { row[0] = i; }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Closest builtin or `PATH` command within two edits of `cmd`, only when
+/// `FALSH_CORRECT=1` so scripts never see suggestions.
+fn suggest_command(shell: &mut Shell, cmd: &str) -> Option<String> {
+    if env::var("FALSH_CORRECT").as_deref() != Ok("1") || cmd.contains('/') { return None; }
+    BUILTINS.iter().copied()
+        .chain(shell.path_commands().iter().map(String::as_str))
+        .map(|name| (edit_distance(cmd, name), name))
+        .filter(|(dist, _)| *dist <= 2)
+        .min_by_key(|(dist, _)| *dist)
+        .map(|(_, name)| name.to_string())
+}
+
+/// Whether `name` is handled in-process by `run_builtin`. A name containing
+/// `/` is always a path to run directly, so `./cd` runs the local script.
+fn is_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "cd" | "pwd" | "addToPath" | "pathTool" | "set" | "repeat" | "exec" | "source" | "." | "please" | "echo" | "printf" | "read" | "test" | "[" | "type" | "which" | "command" | "builtin" | "export" | "unset" | "shift" | "alias" | "unalias" | "complete" | "trap" | "version"
+    )
+}
+
+/// Runs a builtin command; `args[0]` must satisfy `is_builtin`.
+fn run_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    match args[0].as_str() {
+        "cd" => {
+            if args.len() > 1 { change_dir(&args[1])?; }
+            else { return Err("cd: missing argument".into()); }
+        }
+        "pwd" => print_working_dir(),
+        "addToPath" => {
+            let temporary = args.iter().any(|a| a == "--temp");
+            if args.len() > 1 { add_to_path(&args[1], temporary); }
+            else { return Err("addToPath: missing argument".into()); }
+        }
+        "pathTool" => list_path(),
+        "set" => return set_builtin(shell, &args[1..]),
+        "repeat" => return repeat_builtin(shell, &args[1..]),
+        "exec" => return exec_builtin(&args[1..]),
+        "source" | "." => return source_builtin(shell, &args[0], &args[1..]),
+        "please" => return please_builtin(shell),
+        "shift" => return shift_builtin(shell, &args[1..]),
+        "alias" => return alias_builtin(shell, &args[1..]),
+        "unalias" => return unalias_builtin(shell, &args[1..]),
+        "complete" => return complete_builtin(shell, &args[1..]),
+        "trap" => return trap_builtin(shell, &args[1..]),
+        "version" => outln!("{}", version_line()),
+        "echo" => return echo_builtin(&args[1..]),
+        "printf" => return printf_builtin(shell, &args[1..]),
+        "read" => return read_builtin(shell, &args[1..]),
+        "test" | "[" => return test_builtin(&args[0], &args[1..]),
+        "type" | "which" => return type_builtin(shell, &args[0], &args[1..]),
+        "command" => return command_builtin(shell, &args[1..]),
+        // `builtin NAME` only gets here when NAME is not one.
+        "builtin" => if let Some(name) = args.get(1) {
+            return Err(format!("builtin: {}: not a shell builtin", name));
+        },
+        "export" => return export_builtin(shell, &args[1..]),
+        "unset" => return unset_builtin(shell, &args[1..]),
+        other => unreachable!("not a builtin: {}", other),
+    }
+    Ok(0)
+}
+
+/// With `FALSH_FORCE_CHILD_COLOR=1`, sets `CLICOLOR_FORCE=1` and
+/// `FORCE_COLOR=1` for the child so tools that honor them keep color in pipes.
+fn force_child_color(cmd: &mut Command) {
+    if env::var("FALSH_FORCE_CHILD_COLOR").as_deref() == Ok("1") {
+        cmd.env("CLICOLOR_FORCE", "1").env("FORCE_COLOR", "1");
+    }
+}
+
+/// `export NAME=value`, `export NAME+=suffix` and `export NAME` (which
+/// promotes a shell variable); bare `export` lists the environment.
+fn export_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    if args.is_empty() {
+        let listing: String = env::vars().map(|(key, value)| format!("{}={}\n", key, value)).collect();
+        page_output(&listing);
+        return Ok(0);
+    }
+    let mut status = 0;
+    for var_assignment in args {
+        let name = var_assignment.split_once('=').map_or(var_assignment.as_str(), |(key, _)| key);
+        if !parser::is_name(name.strip_suffix('+').unwrap_or(name)) {
+            println!("export: '{}': not a valid identifier", var_assignment);
+            status = 1;
+            continue;
+        }
+        let append = var_assignment.split_once('=')
+            .and_then(|(key, value)| Some((key.strip_suffix('+')?, value)));
+        if let Some((key, suffix)) = append {
+            let value = shell.lookup_var(key).unwrap_or_default() + suffix;
+            shell.vars.remove(key);
+            unsafe{
+            env::set_var(key, value);
+            }
+        } else if let Some(eq_pos) = var_assignment.find('=') {
+            let key = &var_assignment[..eq_pos];
+            let value = &var_assignment[eq_pos+1..];
+            shell.vars.remove(key);
+            unsafe{
+            env::set_var(key, value);
+            }
+        } else if let Some(value) = shell.vars.remove(var_assignment) {
+            unsafe { env::set_var(var_assignment, value); }
+        } else if env::var_os(var_assignment).is_none() {
+            println!("export: invalid syntax '{}', expected VAR=VALUE", var_assignment);
+        }
+    }
+    Ok(status)
+}
+
+/// `echo [-neE] [ARGS]`: `-n` drops the trailing newline, `-e` decodes
+/// backslash escapes and `-E` stops decoding them again. As in bash, only
+/// leading words made up entirely of those letters count as options.
+fn echo_builtin(args: &[String]) -> Result<i32, String> {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut words = args;
+    while let Some(flags) = words.first()
+        .and_then(|word| word.strip_prefix('-'))
+        .filter(|flags| !flags.is_empty() && flags.chars().all(|c| "neE".contains(c)))
+    {
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        words = &words[1..];
+    }
+    let mut text = words.join(" ");
+    if escapes {
+        let (decoded, stop) = decode_escapes(&text);
+        text = decoded;
+        newline &= !stop;
+    }
+    if newline { text.push('\n'); }
+    out!("{}", text);
+    Ok(0)
+}
+
+/// Decodes the backslash escapes of `echo -e` (see `decode_escape`). The
+/// flag is set when `\c` ended the output early.
+fn decode_escapes(text: &str) -> (String, bool) {
+    let mut out = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+        } else if !decode_escape(&mut chars, &mut out, true) {
+            return (out, true);
+        }
+    }
+    (out, false)
+}
+
+/// Decodes the escape after a backslash into `out`: `\n`, `\t`, `\e` and
+/// the other C letters, `\\`, `\xHH`, `\uHHHH`, `\UHHHHHHHH`, and octal,
+/// written `\0nnn` for `echo` and `\nnn` for a `printf` format. Unknown
+/// ones stay as written. Returns false for `\c`, which ends the output.
+fn decode_escape(chars: &mut std::iter::Peekable<std::str::Chars<'_>>, out: &mut String, echo: bool) -> bool {
+    let Some(escape) = chars.next() else {
+        out.push('\\');
+        return true;
+    };
+    let mut number = |radix: u32, max: usize, first: Option<u32>| {
+        let mut value = first;
+        for _ in 0..max {
+            let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix)) else { break };
+            value = Some(value.unwrap_or(0) * radix + digit);
+            chars.next();
+        }
+        value
+    };
+    match escape {
+        'a' => out.push('\x07'),
+        'b' => out.push('\x08'),
+        'c' => return false,
+        'e' | 'E' => out.push('\x1b'),
+        'f' => out.push('\x0c'),
+        'n' => out.push('\n'),
+        'r' => out.push('\r'),
+        't' => out.push('\t'),
+        'v' => out.push('\x0b'),
+        '\\' => out.push('\\'),
+        '0' if echo => out.extend(char::from_u32(number(8, 3, None).unwrap_or(0) & 0xff)),
+        '0'..='7' if !echo => {
+            let first = escape.to_digit(8);
+            out.extend(char::from_u32(number(8, 2, first).unwrap_or(0) & 0xff));
+        }
+        'x' | 'u' | 'U' => {
+            let max = match escape { 'x' => 2, 'u' => 4, _ => 8 };
+            match number(16, max, None) {
+                Some(value) => out.extend(char::from_u32(value)),
+                None => out.extend(['\\', escape]),
+            }
+        }
+        other => out.extend(['\\', other]),
+    }
+    true
+}
+
+/// `printf [-v VAR] FORMAT [ARGS]` writes ARGS as FORMAT says, or stores
+/// the text in VAR. The format is reused until the arguments run out;
+/// missing ones count as empty or 0. It takes `\`-escapes and
+/// `%[flags][width][.precision]` conversions `s b c d i u o x X e E f F g G`
+/// and `%%`, with `*` taking the width or precision from the arguments.
+fn printf_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    let (var, args) = match args {
+        [flag, name, rest @ ..] if flag == "-v" => (Some(name), rest),
+        _ => (None, args),
+    };
+    let Some((format, mut rest)) = args.split_first() else {
+        return Err("printf: usage: printf [-v var] format [arguments]".into());
+    };
+    let mut printf = Printf { out: String::new(), status: 0 };
+    loop {
+        let (used, done) = printf.format(format, rest)?;
+        rest = &rest[used..];
+        if done || used == 0 || rest.is_empty() { break; }
+    }
+    match var {
+        Some(name) if !parser::is_name(name) => return Err(format!("printf: '{}': not a valid identifier", name)),
+        Some(name) => shell.set_var(name, &printf.out),
+        None => out!("{}", printf.out),
+    }
+    Ok(printf.status)
+}
+
+/// The text `printf` has produced so far, and its status, which turns 1
+/// when an argument is not a valid number.
+struct Printf {
+    out: String,
+    status: i32,
+}
+
+/// The flags, width and precision of one `%` conversion.
+#[derive(Default)]
+struct FormatSpec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    /// `sign` then `body`, padded out to the width. Zeros go between the
+    /// two when the `0` flag applies.
+    fn pad(&self, sign: &str, body: &str, zeros: bool) -> String {
+        let fill = self.width.saturating_sub(sign.chars().count() + body.chars().count());
+        if self.left {
+            format!("{}{}{}", sign, body, " ".repeat(fill))
+        } else if zeros && self.zero {
+            format!("{}{}{}", sign, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), sign, body)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        }
+    }
+}
+
+impl Printf {
+    /// Runs through `format` once, taking arguments from `args`. Returns
+    /// how many it used and whether `\c` stopped the output.
+    fn format(&mut self, format: &str, args: &[String]) -> Result<(usize, bool), String> {
+        let mut args = args.iter();
+        let start = args.len();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if !decode_escape(&mut chars, &mut self.out, false) {
+                        return Ok((start - args.len(), true));
+                    }
+                }
+                '%' if chars.peek() == Some(&'%') => {
+                    chars.next();
+                    self.out.push('%');
+                }
+                '%' => {
+                    let mut spec = FormatSpec::default();
+                    while let Some(&flag) = chars.peek().filter(|c| "-+ #0".contains(**c)) {
+                        match flag {
+                            '-' => spec.left = true,
+                            '+' => spec.plus = true,
+                            ' ' => spec.space = true,
+                            '#' => spec.alt = true,
+                            _ => spec.zero = true,
+                        }
+                        chars.next();
+                    }
+                    let mut count = |chars: &mut std::iter::Peekable<std::str::Chars<'_>>, this: &mut Self| {
+                        if chars.next_if_eq(&'*').is_some() {
+                            let arg = args.next().map_or("", String::as_str);
+                            return Some(this.int(arg));
+                        }
+                        let mut digits = String::new();
+                        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                            digits.push(digit);
+                        }
+                        digits.parse::<i64>().ok()
+                    };
+                    if let Some(width) = count(&mut chars, self) {
+                        spec.left |= width < 0;
+                        spec.width = width.unsigned_abs() as usize;
+                    }
+                    if chars.next_if_eq(&'.').is_some() {
+                        spec.precision = Some(count(&mut chars, self).unwrap_or(0).max(0) as usize);
+                    }
+                    let Some(conversion) = chars.next() else {
+                        return Err("printf: '%': missing format character".into());
+                    };
+                    let arg = args.next().map_or("", String::as_str);
+                    if !self.convert(conversion, &spec, arg)? {
+                        return Ok((start - args.len(), true));
+                    }
+                }
+                c => self.out.push(c),
+            }
+        }
+        Ok((start - args.len(), false))
+    }
+
+    /// Appends `arg` converted by `conversion`. Returns false when a `%b`
+    /// argument ended the output with `\c`.
+    fn convert(&mut self, conversion: char, spec: &FormatSpec, arg: &str) -> Result<bool, String> {
+        let text = match conversion {
+            's' | 'b' => {
+                let (text, stop) = if conversion == 'b' { decode_escapes(arg) } else { (arg.to_string(), false) };
+                let text: String = match spec.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                };
+                self.out.push_str(&spec.pad("", &text, false));
+                return Ok(!stop);
+            }
+            'c' => spec.pad("", &arg.chars().next().map(String::from).unwrap_or_default(), false),
+            'd' | 'i' => {
+                let n = self.int(arg);
+                let digits = n.unsigned_abs().to_string();
+                self.integer(spec, spec.sign(n < 0), "", digits)
+            }
+            'u' | 'o' | 'x' | 'X' => {
+                let n = self.int(arg) as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (n.to_string(), ""),
+                    'o' => (format!("{:o}", n), if spec.alt && n != 0 { "0" } else { "" }),
+                    'x' => (format!("{:x}", n), if spec.alt && n != 0 { "0x" } else { "" }),
+                    _ => (format!("{:X}", n), if spec.alt && n != 0 { "0X" } else { "" }),
+                };
+                self.integer(spec, "", prefix, digits)
+            }
+            'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+                let x = self.float(arg);
+                let precision = spec.precision.unwrap_or(6);
+                let body = if !x.is_finite() {
+                    if x.is_nan() { "nan".to_string() } else { "inf".to_string() }
+                } else {
+                    match conversion.to_ascii_lowercase() {
+                        'e' => format_exponent(x.abs(), precision),
+                        'f' => format!("{:.*}", precision, x.abs()),
+                        _ => format_general(x.abs(), precision, spec.alt),
+                    }
+                };
+                let body = if conversion.is_ascii_uppercase() { body.to_uppercase() } else { body };
+                spec.pad(spec.sign(x.is_sign_negative() && !x.is_nan()), &body, x.is_finite())
+            }
+            other => return Err(format!("printf: '%{}': invalid format character", other)),
+        };
+        self.out.push_str(&text);
+        Ok(true)
+    }
+
+    /// An integer conversion: `digits` widened to the precision, after
+    /// `sign` and `prefix`. A precision turns the `0` flag off, as in C.
+    fn integer(&self, spec: &FormatSpec, sign: &str, prefix: &str, digits: String) -> String {
+        match spec.precision {
+            Some(precision) => {
+                let digits = format!("{:0>1$}", digits, precision);
+                spec.pad(&format!("{}{}", sign, prefix), &digits, false)
+            }
+            None => spec.pad(&format!("{}{}", sign, prefix), &digits, true),
+        }
+    }
+
+    /// A numeric argument: decimal, `0x` hex, `0` octal, or `'c` for the
+    /// character's code. Empty counts as 0; anything else is reported and
+    /// counts as 0 too.
+    fn int(&mut self, arg: &str) -> i64 {
+        let text = arg.trim();
+        if let Some(quoted) = text.strip_prefix(['\'', '"']) {
+            return quoted.chars().next().map_or(0, |c| c as i64);
+        }
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None if digits.len() > 1 && digits.starts_with('0') => i64::from_str_radix(&digits[1..], 8),
+            None if digits.is_empty() && !negative => Ok(0),
+            None => digits.parse(),
+        };
+        match value {
+            Ok(value) => if negative { value.wrapping_neg() } else { value },
+            Err(_) => {
+                println!("printf: '{}': invalid number", arg);
+                self.status = 1;
+                0
+            }
+        }
+    }
+
+    fn float(&mut self, arg: &str) -> f64 {
+        let text = arg.trim();
+        match text.parse::<f64>() {
+            Ok(value) => value,
+            Err(_) if text.is_empty() => 0.0,
+            Err(_) => self.int(arg) as f64,
+        }
+    }
+}
+
+/// `x` in C's `%e` form: one digit before the point, `precision` after,
+/// and an exponent of at least two digits.
+fn format_exponent(x: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, x);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+/// `x` in C's `%g` form: `%e` for very small or large numbers, `%f`
+/// otherwise, with `precision` significant digits and, unless `alt`, no
+/// trailing zeros.
+fn format_general(x: f64, precision: usize, alt: bool) -> String {
+    let precision = precision.max(1);
+    let exponent = if x == 0.0 {
+        0
+    } else {
+        let text = format!("{:.*e}", precision - 1, x);
+        text.split_once('e').and_then(|(_, exponent)| exponent.parse::<i32>().ok()).unwrap_or(0)
+    };
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(x, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, x)
+    };
+    if alt {
+        return text;
+    }
+    let (number, exponent) = match text.find('e') {
+        Some(idx) => text.split_at(idx),
+        None => (text.as_str(), ""),
+    };
+    let number = if number.contains('.') { number.trim_end_matches('0').trim_end_matches('.') } else { number };
+    format!("{}{}", number, exponent)
+}
+
+/// `read [-r] [-s] [-p PROMPT] [-t SECS] [NAME...]` reads a line from
+/// stdin and splits it on whitespace into the NAMEs, the last one getting
+/// the rest of the line; with no NAME it all goes into `REPLY`. Unless
+/// `-r` is given, a backslash escapes the next character and one at the
+/// end of a line joins the next. `-p` shows PROMPT when stdin is a
+/// terminal, `-s` hides what is typed, and `-t` gives up after SECS
+/// seconds. The status is 1 at end of input and 142 on a timeout.
+fn read_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    let mut raw = false;
+    let mut silent = false;
+    let mut prompt = None;
+    let mut timeout = None;
+    let mut args = args.iter();
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-r" => raw = true,
+            "-s" => silent = true,
+            "-p" => prompt = Some(args.next().ok_or("read: -p: option requires an argument")?),
+            "-t" => {
+                let secs = args.next().ok_or("read: -t: option requires an argument")?;
+                let duration = secs.parse().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(|| format!("read: {}: invalid timeout specification", secs))?;
+                timeout = Some(duration);
+            }
+            name if parser::is_name(name) => names.push(name.to_string()),
+            other => return Err(format!("read: '{}': not a valid identifier", other)),
+        }
+    }
+
+    let terminal = io::stdin().is_terminal();
+    if let Some(prompt) = prompt.filter(|_| terminal) {
+        eprint!("{}", prompt);
+    }
+    let echo = (silent && terminal).then(|| set_terminal_echo(false));
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut bytes = Vec::new();
+    let status = loop {
+        match read_byte(deadline) {
+            Ok(Some(b'\n')) if !raw && bytes.iter().rev().take_while(|b| **b == b'\\').count() % 2 == 1 => {
+                bytes.pop();
+            }
+            Ok(Some(b'\n')) => break 0,
+            Ok(Some(byte)) => bytes.push(byte),
+            Ok(None) => break 1,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => break 142,
+            Err(e) => return Err(format!("read: {}", e)),
+        }
+    };
+    if let Some(echo) = echo {
+        set_terminal_echo(echo);
+        eprintln!();
+    }
+
+    // Each character, and whether a backslash escaped it.
+    let mut chars = Vec::new();
+    let text = String::from_utf8_lossy(&bytes);
+    let mut text = text.chars();
+    while let Some(c) = text.next() {
+        match c {
+            '\\' if !raw => chars.extend(text.next().map(|c| (c, true))),
+            c => chars.push((c, false)),
+        }
+    }
+    let blank = |(c, escaped): &(char, bool)| !escaped && c.is_whitespace();
+    if names.is_empty() {
+        shell.set_var("REPLY", &chars.iter().map(|(c, _)| c).collect::<String>());
+        return Ok(status);
+    }
+    let mut rest = &chars[..];
+    for (idx, name) in names.iter().enumerate() {
+        rest = &rest[rest.iter().take_while(|c| blank(c)).count()..];
+        let len = if idx + 1 == names.len() {
+            rest.len() - rest.iter().rev().take_while(|c| blank(c)).count()
+        } else {
+            rest.iter().take_while(|c| !blank(c)).count()
+        };
+        shell.set_var(name, &rest[..len].iter().map(|(c, _)| c).collect::<String>());
+        rest = &rest[len..];
+    }
+    Ok(status)
+}
+
+/// One byte from stdin, read unbuffered so the rest stays for whoever reads
+/// next; `None` at end of input. Fails with `TimedOut` once `deadline`
+/// passes.
+#[cfg(unix)]
+fn read_byte(deadline: Option<Instant>) -> io::Result<Option<u8>> {
+    if let Some(deadline) = deadline {
+        // Checked before polling too, as input that never stops coming
+        // would otherwise keep it waiting.
+        let wait = deadline.checked_duration_since(Instant::now()).ok_or(io::ErrorKind::TimedOut)?;
+        let mut poll = libc::pollfd { fd: 0, events: libc::POLLIN, revents: 0 };
+        let ready = unsafe { libc::poll(&mut poll, 1, wait.as_millis().min(i32::MAX as u128) as i32) };
+        if ready < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if ready == 0 {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+    }
+    let mut byte = 0u8;
+    loop {
+        match unsafe { libc::read(0, (&mut byte as *mut u8).cast(), 1) } {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted { return Err(err); }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn read_byte(_deadline: Option<Instant>) -> io::Result<Option<u8>> {
+    use std::io::Read;
+
+    let mut byte = [0u8];
+    Ok((io::stdin().read(&mut byte)? == 1).then_some(byte[0]))
+}
+
+/// Turns the terminal's echo on or off, returning whether it was on.
+#[cfg(unix)]
+fn set_terminal_echo(on: bool) -> bool {
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(0, &mut termios) } < 0 {
+        return true;
+    }
+    let was_on = termios.c_lflag & libc::ECHO != 0;
+    if on { termios.c_lflag |= libc::ECHO } else { termios.c_lflag &= !libc::ECHO }
+    unsafe { libc::tcsetattr(0, libc::TCSANOW, &termios); }
+    was_on
+}
+
+#[cfg(not(unix))]
+fn set_terminal_echo(_on: bool) -> bool {
+    true
+}
+
+/// `unset [-v] NAME...` removes shell and environment variables alike.
+/// Names that are not set are skipped without complaint.
+fn unset_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, String> {
+    let names = match args.first().map(String::as_str) {
+        Some("-v") => &args[1..],
+        Some("-f") => return Err("unset: -f: shell functions are not supported".into()),
+        _ => args,
+    };
+    let mut status = 0;
+    for name in names {
+        if !parser::is_name(name) {
+            println!("unset: '{}': not a valid identifier", name);
+            status = 1;
+            continue;
+        }
+        shell.vars.remove(name);
+        unsafe { env::remove_var(name); }
+    }
+    Ok(status)
+}
+
+/// `test EXPR` and `[ EXPR ]`: file checks (`-e -f -d -L -r -w -x -s`,
+/// ...), string checks (`-n -z = != < >`), integer comparisons (`-eq -lt`,
+/// ...) and file comparisons (`-nt -ot -ef`), joined with `!`, `-a`, `-o`
+/// and parentheses. The status is 0 when EXPR holds, 1 when it does not
+/// and 2 when it cannot be parsed.
+fn test_builtin(name: &str, args: &[String]) -> Result<i32, String> {
+    let args = if name == "[" {
+        match args.split_last() {
+            Some((last, rest)) if last == "]" => rest,
+            _ => {
+                println!("[: missing ']'");
+                return Ok(2);
+            }
+        }
+    } else {
+        args
+    };
+    let mut test = Test { args, pos: 0 };
+    let result = if args.is_empty() { Ok(false) } else { test.or() };
+    let result = result.and_then(|value| match test.peek(0) {
+        Some(extra) => Err(format!("{}: unexpected argument", extra)),
+        None => Ok(value),
+    });
+    match result {
+        Ok(value) => Ok(if value { 0 } else { 1 }),
+        Err(e) => {
+            println!("{}: {}", name, e);
+            Ok(2)
+        }
+    }
+}
+
+/// A `test` expression, read one argument at a time.
+struct Test<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Test<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let arg = self.peek(0).ok_or("argument expected")?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut value = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            value |= self.and()?;
+        }
+        Ok(value)
+    }
 
-fn split_args(input: &str) -> Vec<String> {
-    let mut args = Vec::new();
-    let mut current = String::new();
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut chars = input.chars().peekable();
+    fn and(&mut self) -> Result<bool, String> {
+        let mut value = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            value &= self.not()?;
+        }
+        Ok(value)
+    }
 
-    while let Some(&ch) = chars.peek() {
-        match ch {
-            '\'' if !in_double => { in_single = !in_single; chars.next(); }
-            '"' if !in_single => { in_double = !in_double; chars.next(); }
-            ' ' if !in_single && !in_double => {
-                if !current.is_empty() { args.push(current.clone()); current.clear(); }
-                chars.next();
-            }
-            _ => { current.push(ch); chars.next(); }
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("!") && self.peek(1).is_some() {
+            self.pos += 1;
+            return Ok(!self.not()?);
         }
+        self.primary()
     }
-    if !current.is_empty() { args.push(current); }
-    args
-}
 
-fn expand_globs(args: Vec<String>) -> Vec<String> {
-    let mut expanded = Vec::new();
-    for arg in args {
-        if arg.contains('*') || arg.contains('?') {
-            for entry in glob(&arg).unwrap().filter_map(Result::ok) {
-                expanded.push(entry.to_string_lossy().to_string());
-            }
-        } else {
-            expanded.push(arg);
+    fn primary(&mut self) -> Result<bool, String> {
+        // A binary operator in second place wins, so `[ -f = -f ]`
+        // compares two strings.
+        if let (Some(left), Some(op), Some(right)) = (self.peek(0), self.peek(1), self.peek(2))
+            && is_test_binary(op)
+        {
+            self.pos += 3;
+            return test_binary(left, op, right);
+        }
+        let arg = self.next()?;
+        if arg == "(" && self.peek(0).is_some() {
+            let value = self.or()?;
+            return match self.next() {
+                Ok(")") => Ok(value),
+                _ => Err("expected ')'".into()),
+            };
         }
+        if arg.len() == 2 && arg.starts_with('-') && arg != "--"
+            && let Some(operand) = self.peek(0)
+        {
+            self.pos += 1;
+            return test_unary(arg, operand);
+        }
+        Ok(!arg.is_empty())
     }
-    expanded
 }
 
-fn change_dir(path: &str) {
-    if let Err(e) = env::set_current_dir(path) {
-        println!("cd failed: {}", e);
-    }
+fn is_test_binary(op: &str) -> bool {
+    matches!(op, "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" | "-nt" | "-ot" | "-ef")
 }
 
-fn print_working_dir() {
-    match env::current_dir() {
-        Ok(path) => println!("{}", path.display()),
-        Err(e) => println!("pwd failed: {}", e),
+fn test_unary(op: &str, operand: &str) -> Result<bool, String> {
+    match op {
+        "-n" => return Ok(!operand.is_empty()),
+        "-z" => return Ok(operand.is_empty()),
+        "-t" => return Ok(operand.trim().parse::<i32>().is_ok_and(fd_is_terminal)),
+        _ => {}
     }
+    let path = Path::new(operand);
+    let meta = if matches!(op, "-L" | "-h") { fs::symlink_metadata(path) } else { fs::metadata(path) };
+    let Ok(meta) = meta else {
+        return match op {
+            "-e" | "-f" | "-d" | "-L" | "-h" | "-r" | "-w" | "-x" | "-s" | "-p" | "-S" | "-b" | "-c" | "-g" | "-u" | "-k" => Ok(false),
+            _ => Err(format!("{}: unary operator expected", op)),
+        };
+    };
+    Ok(match op {
+        "-e" => true,
+        "-f" => meta.is_file(),
+        "-d" => meta.is_dir(),
+        "-L" | "-h" => meta.file_type().is_symlink(),
+        "-s" => meta.len() > 0,
+        "-r" => file_access(path, 4, &meta),
+        "-w" => file_access(path, 2, &meta),
+        "-x" => file_access(path, 1, &meta),
+        _ => return file_kind_test(op, &meta),
+    })
 }
 
-fn get_persistent_path_file() -> PathBuf {
-    let mut path = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-    path.push(".falsh_path");
-    path
+/// The file type and mode bit tests that only mean something on unix.
+#[cfg(unix)]
+fn file_kind_test(op: &str, meta: &fs::Metadata) -> Result<bool, String> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    let file_type = meta.file_type();
+    let mode = meta.permissions().mode();
+    Ok(match op {
+        "-p" => file_type.is_fifo(),
+        "-S" => file_type.is_socket(),
+        "-b" => file_type.is_block_device(),
+        "-c" => file_type.is_char_device(),
+        "-u" => mode & 0o4000 != 0,
+        "-g" => mode & 0o2000 != 0,
+        "-k" => mode & 0o1000 != 0,
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
 }
 
-fn get_falshrc_file() -> PathBuf {
-    let mut path = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-    path.push(".falshrc");
-    path
+#[cfg(not(unix))]
+fn file_kind_test(op: &str, _meta: &fs::Metadata) -> Result<bool, String> {
+    match op {
+        "-p" | "-S" | "-b" | "-c" | "-u" | "-g" | "-k" => Ok(false),
+        _ => Err(format!("{}: unary operator expected", op)),
+    }
 }
 
-fn load_persistent_paths() -> Vec<String> {
-    let file = get_persistent_path_file();
-    if !file.exists() { return vec![]; }
-    BufReader::new(File::open(file).unwrap())
-        .lines()
-        .filter_map(Result::ok)
-        .collect()
+/// Whether the shell may read (4), write (2) or execute (1) `path`.
+#[cfg(unix)]
+fn file_access(path: &Path, mode: i32, _meta: &fs::Metadata) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else { return false };
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
 }
 
-fn save_persistent_paths(paths: &[String]) {
-    let file = get_persistent_path_file();
-    let mut f = File::create(file).unwrap();
-    for p in paths { writeln!(f, "{}", p).unwrap(); }
+#[cfg(not(unix))]
+fn file_access(_path: &Path, mode: i32, meta: &fs::Metadata) -> bool {
+    match mode {
+        2 => !meta.permissions().readonly(),
+        1 => meta.is_dir() || meta.is_file(),
+        _ => true,
+    }
 }
 
-fn add_to_path(user_input: &str, temporary: bool) {
-    let actual_path = PathBuf::from(user_input);
-    let path_to_add = match fs::metadata(&actual_path) {
-        Ok(meta) => {
-            if meta.is_file() {
-                actual_path.parent().map(|p| p.to_path_buf()).unwrap_or(actual_path.clone())
-            } else { actual_path.clone() }
-        }
-        Err(_) => {
-            println!("Warning: path {} does not exist.", user_input);
-            actual_path.clone()
-        }
-    };
-
-    let add_str = path_to_add.to_string_lossy().to_string();
+#[cfg(unix)]
+fn fd_is_terminal(fd: i32) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
 
-    if !temporary {
-        let mut paths = load_persistent_paths();
-        if !paths.iter().any(|p| p == user_input) {
-            paths.push(user_input.to_string());
-            save_persistent_paths(&paths);
-        }
+#[cfg(not(unix))]
+fn fd_is_terminal(fd: i32) -> bool {
+    match fd {
+        0 => io::stdin().is_terminal(),
+        1 => io::stdout().is_terminal(),
+        2 => io::stderr().is_terminal(),
+        _ => false,
     }
+}
 
-    let mut path_env = env::var("PATH").unwrap_or_default();
-    if !path_env.split(':').any(|p| p == add_str) {
-        if !path_env.is_empty() { path_env.push(':'); }
-        path_env.push_str(&add_str);
-        unsafe {
-            env::set_var("PATH", &path_env);
-        }
-    }
+fn test_binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    let integer = |s: &str| s.trim().parse::<i64>().map_err(|_| format!("{}: integer expression expected", s));
+    let modified = |s: &str| fs::metadata(s).and_then(|meta| meta.modified()).ok();
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(a), Some(b)) => a > b,
+            (a, _) => a.is_some(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(a), Some(b)) => a < b,
+            (_, b) => b.is_some(),
+        },
+        "-ef" => same_file(left, right),
+        _ => return Err(format!("{}: binary operator expected", op)),
+    })
 }
 
-fn prompt_line(prompt: &str) -> Option<String> {
-    disable_raw_mode().ok();
-    let mut rl = Editor::<(), rustyline::history::DefaultHistory>::new().ok()?;
-    let out = rl.readline(&format!("\r{}", prompt)).ok();
-    enable_raw_mode().ok();
-    out.map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+#[cfg(unix)]
+fn same_file(a: &str, b: &str) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
 }
 
-fn list_path() {
-    let mut paths = load_persistent_paths();
-    enable_raw_mode().unwrap();
-    let mut selected: usize = 0;
+#[cfg(not(unix))]
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
 
-    loop {
-        execute!(io::stdout(), Clear(ClearType::All), cursor::MoveTo(0, 0)).unwrap();
-        println!("Use ↑/↓ to navigate, Enter to delete/open '+', Esc to exit:\n");
+/// What a command name runs, in the order the executor tries them.
+enum CommandKind {
+    Alias(String),
+    Builtin,
+    File(PathBuf),
+}
 
-        for (i, path) in paths.iter().enumerate() {
-            if i == selected { println!("> {}", path); }
-            else { println!("  {}", path); }
+/// Every meaning `name` has as a command, the one that runs first. A name
+/// with a `/` is only ever a path.
+fn command_kinds(shell: &Shell, name: &str) -> Vec<CommandKind> {
+    let mut kinds = Vec::new();
+    if name.contains('/') {
+        if is_executable(Path::new(name)) {
+            kinds.push(CommandKind::File(PathBuf::from(name)));
         }
-        let plus_idx = paths.len();
-        if selected == plus_idx { println!("> [+ Add new path]"); }
-        else { println!("  [+ Add new path]"); }
+        return kinds;
+    }
+    if let Some(value) = shell.aliases.get(name) {
+        kinds.push(CommandKind::Alias(value.clone()));
+    }
+    if is_builtin(name) {
+        kinds.push(CommandKind::Builtin);
+    }
+    let path = env::var_os("PATH").unwrap_or_default();
+    kinds.extend(
+        env::split_paths(&path)
+            .map(|dir| dir.join(name))
+            .filter(|file| is_executable(file))
+            .map(CommandKind::File),
+    );
+    kinds
+}
 
-        if let Event::Key(KeyEvent { code, .. }) = event::read().unwrap() {
-            match code {
-                KeyCode::Up => { if selected > 0 { selected -= 1; } else { selected = plus_idx; } }
-                KeyCode::Down => { if selected < plus_idx { selected += 1; } else { selected = 0; } }
-                KeyCode::Enter => {
-                    if selected == plus_idx {
-                        if let Some(newp) = prompt_line("Enter path to add: ") {
-                            add_to_path(&newp, false);
-                            paths = load_persistent_paths();
-                        }
-                    } else if !paths.is_empty() {
-                        paths.remove(selected);
-                        save_persistent_paths(&paths);
-                        if selected >= paths.len() && selected > 0 { selected -= 1; }
-                    }
-                }
-                KeyCode::Esc => break,
+/// `type [-a] [-t] [-p] NAME...` says whether each NAME is an alias, a
+/// builtin or a file on `PATH`. `-a` lists every match instead of the
+/// first, `-t` prints just `alias`, `builtin` or `file`, and `-p` only
+/// the path of a file. `which [-a] NAME...` prints the same, the way zsh
+/// does. The status is 1 when some NAME is not found.
+fn type_builtin(shell: &Shell, name: &str, args: &[String]) -> Result<i32, String> {
+    let mut all = false;
+    let mut terse = false;
+    let mut path_only = false;
+    let mut names = args;
+    while let Some((flag, rest)) = names.split_first()
+        && flag.len() > 1
+        && flag.starts_with('-')
+    {
+        for c in flag[1..].chars() {
+            match c {
+                'a' => all = true,
+                't' if name == "type" => terse = true,
+                'p' if name == "type" => path_only = true,
+                _ => return Err(format!("{}: -{}: invalid option", name, c)),
+            }
+        }
+        names = rest;
+    }
+    let mut status = 0;
+    for command in names {
+        let mut kinds = command_kinds(shell, command);
+        if kinds.is_empty() {
+            match name {
+                "which" => println!("{} not found", command),
+                _ if !terse && !path_only => println!("type: {}: not found", command),
                 _ => {}
             }
+            status = 1;
+            continue;
+        }
+        if !all {
+            kinds.truncate(1);
+        }
+        for kind in kinds {
+            let line = match kind {
+                CommandKind::Alias(_) if path_only => continue,
+                CommandKind::Alias(_) if terse => "alias".to_string(),
+                CommandKind::Alias(value) if name == "which" => format!("{}: aliased to {}", command, value),
+                CommandKind::Alias(value) => format!("{} is aliased to '{}'", command, value),
+                CommandKind::Builtin if path_only => continue,
+                CommandKind::Builtin if terse => "builtin".to_string(),
+                CommandKind::Builtin if name == "which" => format!("{}: shell built-in command", command),
+                CommandKind::Builtin => format!("{} is a shell builtin", command),
+                CommandKind::File(_) if terse => "file".to_string(),
+                CommandKind::File(path) if path_only || name == "which" => path.display().to_string(),
+                CommandKind::File(path) => format!("{} is {}", command, path.display()),
+            };
+            outln!("{}", line);
         }
     }
-    disable_raw_mode().unwrap();
+    Ok(status)
 }
 
-fn load_persistent_into_env() {
-    for user_entry in load_persistent_paths() {
-        add_to_path(&user_entry, true);
+/// `command -v NAME...` prints how each NAME would run: the path of a
+/// file, the name of a builtin, or the `alias` line that defines it.
+/// `command -V` describes them like `type`. Running `command NAME ARGS`
+/// itself is handled where pipelines are spawned.
+fn command_builtin(shell: &Shell, args: &[String]) -> Result<i32, String> {
+    let (flag, names) = match args.split_first() {
+        Some((flag, names)) if flag == "-v" || flag == "-V" => (flag, names),
+        Some((flag, _)) => return Err(format!("command: {}: invalid option", flag)),
+        None => return Ok(0),
+    };
+    if flag == "-V" {
+        return type_builtin(shell, "type", names);
+    }
+    let mut status = 0;
+    for name in names {
+        match command_kinds(shell, name).into_iter().next() {
+            Some(CommandKind::Alias(value)) => outln!("{}", alias_line(name, &value)),
+            Some(CommandKind::Builtin) => outln!("{}", name),
+            Some(CommandKind::File(path)) => outln!("{}", path.display()),
+            None => status = 1,
+        }
     }
+    Ok(status)
 }
 
-/// ------------------- UPDATED EXECUTE_LINE -------------------
-fn execute_line(input: &str) -> Result<(), String> {
-    if input.is_empty() { return Ok(()); }
-
-    let pipeline: Vec<&str> = input.split('|').map(|s| s.trim()).collect();
-    let mut previous_output: Option<Stdio> = None;
-
-    for (i, segment) in pipeline.iter().enumerate() {
-        let mut args = split_args(segment);
-        if args.is_empty() { continue; }
+/// Describes a failed spawn, naming the stage when it is part of a pipeline,
+/// along with the status the stage gets: 127 when the command is missing,
+/// 126 when it exists but cannot be run.
+fn spawn_error(err: &io::Error, cmd: &str, stage: usize, pipeline: &Pipeline) -> (String, i32) {
+    let (reason, status) = if cmd.contains('/') && Path::new(cmd).is_dir() {
+        ("is a directory".to_string(), 126)
+    } else {
+        match err.kind() {
+            io::ErrorKind::NotFound => ("command not found".to_string(), 127),
+            io::ErrorKind::PermissionDenied => ("permission denied".to_string(), 126),
+            _ => (err.to_string(), 126),
+        }
+    };
+    let msg = if pipeline.commands.len() > 1 {
+        format!("falsh: stage {} '{}': {}", stage + 1, pipeline.commands[stage].text, reason)
+    } else {
+        format!("falsh: {}: {}", cmd, reason)
+    };
+    (msg, status)
+}
 
-        match args[0].as_str() {
-            "cd" => {
-                if args.len() > 1 { change_dir(&args[1]); } 
-                else { return Err("cd: missing argument".into()); }
-                continue;
+fn spawn_pipeline(
+    shell: &mut Shell,
+    pipeline: &Pipeline,
+    previous_output: &mut Option<File>,
+    children: &mut Vec<(usize, Child)>,
+    statuses: &mut [i32],
+) -> Result<(), String> {
+    let last = pipeline.commands.len() - 1;
+    for (i, command) in pipeline.commands.iter().enumerate() {
+        let mut words = command.words.as_slice();
+        let mut assignments = Vec::new();
+        while let Some((name, value)) = words.first().and_then(Word::assignment) {
+            assignments.push((name, expand_single(shell, &value)?));
+            words = &words[1..];
+        }
+        if let Some(group) = &command.group && pipeline.commands.len() == 1 && !pipeline.background {
+            let redirs = open_redirections(shell, command)?;
+            let saved_fds = redirect_shell_fds(redirs)?;
+            let result = run_list(shell, &group.list);
+            restore_fds(saved_fds);
+            statuses[i] = result?;
+            continue;
+        }
+        // Inside a pipeline a group runs as a subshell, like in bash.
+        let body = command.subshell.as_ref().or(command.group.as_ref().map(|group| &group.text));
+        let args = match body {
+            Some(body) => subshell_args(shell, body)?,
+            None => expand_words(shell, words)?,
+        };
+        // Aliases are only looked up for the first word, so all `command
+        // NAME` and `builtin NAME` have left to do is step aside for NAME.
+        let mut args = args;
+        loop {
+            match (args.first().map(String::as_str), args.get(1).map(String::as_str)) {
+                (Some("command"), Some("--")) if args.len() > 2 => { args.drain(..2); }
+                (Some("command"), Some(next)) if !next.starts_with('-') => { args.remove(0); }
+                (Some("builtin"), Some(next)) if is_builtin(next) => { args.remove(0); }
+                _ => break,
             }
-            "pwd" => { print_working_dir(); continue; }
-            "addToPath" => {
-                let temporary = args.iter().any(|a| a == "--temp");
-                if args.len() > 1 { add_to_path(&args[1], temporary); } 
-                else { return Err("addToPath: missing argument".into()); }
-                continue;
+        }
+
+        if args.is_empty() {
+            // `> file` on its own still creates the file.
+            drop(open_redirections(shell, command)?);
+            for (key, value) in &assignments {
+                shell.set_var(key, value);
             }
-            "pathTool" => { list_path(); continue; }
-            "export" => {
-                if args.len() > 1 {
-                    for var_assignment in &args[1..] {
-                        if let Some(eq_pos) = var_assignment.find('=') {
-                            let key = &var_assignment[..eq_pos];
-                            let value = &var_assignment[eq_pos+1..];
-                            unsafe{
-                            env::set_var(key, value);
-                            }
-                        } else {
-                            println!("export: invalid syntax '{}', expected VAR=VALUE", var_assignment);
-                        }
+            statuses[i] = 0;
+            continue;
+        }
+
+        if is_builtin(&args[0]) {
+            // A later stage reads the one before, like any other command.
+            let mut input = previous_output.take();
+            let mut run = |shell: &mut Shell| {
+                let mut redirs = open_redirections(shell, command)?;
+                if let Some(input) = input.take() {
+                    redirs.actions.insert(0, (0, RedirectTarget::File(input)));
+                }
+                let saved_fds = redirect_shell_fds(redirs)?;
+                // `NAME=value builtin` sees the variables only while it runs.
+                let saved: Vec<_> = assignments.iter().map(|(key, _)| (key, env::var_os(key))).collect();
+                for (key, value) in &assignments {
+                    unsafe { env::set_var(key, value); }
+                }
+                let result = run_builtin(shell, &args);
+                for (key, old) in saved {
+                    match old {
+                        Some(old) => unsafe { env::set_var(key, old) },
+                        None => unsafe { env::remove_var(key) },
                     }
+                }
+                // A bare `exec > file` keeps its redirections for the session.
+                if args.len() == 1 && args[0] == "exec" {
+                    keep_fds(saved_fds);
                 } else {
-                    for (key, value) in env::vars() {
-                        println!("{}={}", key, value);
+                    restore_fds(saved_fds);
+                }
+                result
+            };
+            // Before the last stage, the builtin's output is collected and
+            // fed to the next one.
+            statuses[i] = if i < last {
+                let (result, output) = collect_stdout(|| run(shell)).map_err(|e| format!("{}: {}", args[0], e))?;
+                *previous_output = Some(text_pipe(String::from_utf8_lossy(&output).into_owned())?);
+                result?
+            } else {
+                run(shell)?
+            };
+            continue;
+        }
+
+        let redirs = open_redirections(shell, command)?;
+        // Pipes are connected first and redirections applied on top, so an
+        // explicit `<` or `>` wins over the pipe as in bash; a stage whose
+        // output went to a file leaves the next one reading nothing.
+        let stdin_source = previous_output.take().map_or(Stdio::inherit(), Stdio::from);
+        let stdout_target = if i < last { Stdio::piped() } else { Stdio::inherit() };
+
+        let mut cmd = Command::new(&args[0]);
+        cmd.args(&args[1..])
+            .envs(assignments)
+            .stdin(stdin_source)
+            .stdout(stdout_target);
+        wire_fds(&mut cmd, &redirs)?;
+        force_child_color(&mut cmd);
+        // A background job gets its own process group, so Ctrl-C at the
+        // prompt or in a later command does not reach it.
+        #[cfg(unix)]
+        if pipeline.background {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(children.first().map_or(0, |(_, child)| child.id() as i32));
+        }
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let (mut msg, status) = spawn_error(&e, &args[0], i, pipeline);
+                if status == 127 && let Some(guess) = suggest_command(shell, &args[0]) {
+                    if pipeline.commands.len() == 1 {
+                        msg = format!("falsh: command not found: {}", args[0]);
                     }
+                    msg.push_str(&format!(". Did you mean '{}'?", guess));
                 }
+                println!("{}", msg);
+                statuses[i] = status;
+                // The next stage reads nothing rather than the terminal.
+                *previous_output = Some(null_input()?);
                 continue;
             }
-            _ => {}
-        }
+        };
 
-        let mut stdin_source = previous_output.unwrap_or(Stdio::inherit());
-        let mut stdout_target = Stdio::inherit();
+        drop(redirs);
+        *previous_output = child.stdout.take().map(stdout_file);
+        children.push((i, child));
+    }
 
-        if let Some(pos) = args.iter().position(|x| x == ">") {
-            if pos + 1 < args.len() {
-                stdout_target = Stdio::from(File::create(&args[pos + 1]).map_err(|e| e.to_string())?);
-                args.truncate(pos);
-            } else { return Err("Syntax error: '>' requires a filename".into()); }
-        }
+    Ok(())
+}
 
-        if let Some(pos) = args.iter().position(|x| x == "<") {
-            if pos + 1 < args.len() {
-                stdin_source = Stdio::from(File::open(&args[pos + 1]).map_err(|e| e.to_string())?);
-                args.truncate(pos);
-            } else { return Err("Syntax error: '<' requires a filename".into()); }
-        }
+/// The null device opened for reading, for stages that should see no input.
+fn null_input() -> Result<File, String> {
+    let path = if cfg!(windows) { "NUL" } else { "/dev/null" };
+    File::open(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// A child's stdout as a `File`, so a builtin later in the pipeline can
+/// read it as well as a command can.
+#[cfg(unix)]
+fn stdout_file(stdout: ChildStdout) -> File {
+    File::from(std::os::fd::OwnedFd::from(stdout))
+}
 
-        let args_expanded = expand_globs(args[1..].to_vec());
+#[cfg(not(unix))]
+fn stdout_file(stdout: ChildStdout) -> File {
+    File::from(std::os::windows::io::OwnedHandle::from(stdout))
+}
 
-        let mut child = Command::new(&args[0])
-            .args(&args_expanded)
-            .stdin(stdin_source)
-            .stdout(if i < pipeline.len() - 1 { Stdio::piped() } else { stdout_target })
-            .spawn()
-            .map_err(|e| format!("Command failed: {} ({})", e, args[0]))?;
+/// Colors are off when `NO_COLOR` is set (https://no-color.org) or stdout
+/// is not a terminal.
+fn color_enabled() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal()
+}
 
-        previous_output = child.stdout.take().map(Stdio::from);
-        child.wait().map_err(|e| e.to_string())?;
+/// Sets the terminal title via OSC 0 when `FALSH_SET_TITLE=1` and stdout
+/// is a terminal.
+fn set_title(title: &str) {
+    if env::var("FALSH_SET_TITLE").as_deref() != Ok("1") || !io::stdout().is_terminal() { return; }
+    print!("\x1B]0;falsh: {}\x07", title);
+    io::stdout().flush().ok();
+}
+
+/// Titles the terminal after the command a line typed at the prompt runs:
+/// the first word of its first command past any `NAME=value`, once aliases
+/// are expanded. Only the prompt calls this, so `source`, traps and
+/// `PROMPT_COMMAND` leave the title alone.
+fn set_command_title(shell: &Shell, input: &str) {
+    let Ok(list) = parse_line(shell, input) else { return };
+    let name = list.commands().next().and_then(|command| command.words.iter().find(|word| word.assignment().is_none()));
+    if let Some(word) = name {
+        set_title(&word.unquoted());
     }
+}
 
-    Ok(())
+/// The prompt's trailing `>`: green after success, red after a failure.
+fn prompt_marker(last_status: i32) -> String {
+    if !color_enabled() {
+        return ">".to_string();
+    }
+    let color = if last_status == 0 { 32 } else { 31 };
+    format!("\x1B[{}m>\x1B[0m", color)
 }
 
 /// ------------------- LOAD .FALSHRC WITH LINE NUMBERS -------------------
-fn load_falshrc() {
-    let file = get_falshrc_file();
-    if !file.exists() { return; }
+/// Runs a file line by line in the current shell, returning the status of
+/// the last line. Files already being sourced are refused so rc files that
+/// include each other cannot loop forever.
+fn source_file(shell: &mut Shell, file: &Path) -> Result<i32, String> {
+    let canonical = fs::canonicalize(file).map_err(|e| format!("source: {}: {}", file.display(), e))?;
+    if shell.sourcing.contains(&canonical) {
+        return Err(format!("source: recursive include detected: {}", file.display()));
+    }
 
-    let lines = match BufReader::new(File::open(file).unwrap())
-        .lines()
-        .collect::<Result<Vec<_>, _>>() {
-            Ok(l) => l,
-            Err(e) => {
-                println!("Failed to read ~/.falshrc: {}", e);
-                return;
-            }
+    let lines = File::open(&canonical)
+        .and_then(|f| BufReader::new(f).lines().collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+
+    shell.sourcing.push(canonical);
+    let mut status = 0;
+    let mut idx = 0;
+    while idx < lines.len() {
+        let start = idx;
+        let mut command = lines[idx].trim().to_string();
+        idx += 1;
+        if command.is_empty() || command.starts_with('#') { continue; }
+        // A line ending in `\` goes on, and a here-document's body follows
+        // on the next lines, untouched.
+        while parser::incomplete(&command) && idx < lines.len() {
+            command.push('\n');
+            command.push_str(&lines[idx]);
+            idx += 1;
+        }
+
+        if let Err(e) = run_line(shell, &command) {
+            println!("Error executing line {} in {}: '{}': {}", start + 1, file.display(), lines[start].trim(), e);
+        }
+        status = shell.last_status;
+        if shell.errexit_stops(status) { break; }
+    }
+    shell.sourcing.pop();
+    Ok(status)
+}
+
+/// `source FILE [ARGS]` (or `. FILE [ARGS]`) runs FILE in the current
+/// shell. Any ARGS stand in for the positional parameters while it runs.
+fn source_builtin(shell: &mut Shell, name: &str, args: &[String]) -> Result<i32, String> {
+    let Some((file, rest)) = args.split_first() else {
+        return Err(format!("{}: missing argument", name));
     };
+    if rest.is_empty() {
+        return source_file(shell, Path::new(file));
+    }
+    let saved = std::mem::replace(&mut shell.positional, rest.to_vec());
+    let status = source_file(shell, Path::new(file));
+    shell.positional = saved;
+    status
+}
+
+/// Sources the startup file. The default falshrc may be absent; a file
+/// named with `--rcfile` must exist.
+fn load_falshrc(shell: &mut Shell, file: &Path, explicit: bool) {
+    if !explicit && !file.exists() { return; }
+    if let Err(e) = source_file(shell, file) {
+        println!("{}", e);
+    }
+}
+
+/// Runs `$PROMPT_COMMAND`, if set, keeping `$?` from the user's last command.
+fn run_prompt_command(shell: &mut Shell) {
+    let Some(command) = shell.lookup_var("PROMPT_COMMAND").filter(|c| !c.trim().is_empty()) else { return };
+    let status = shell.last_status;
+    if let Err(e) = run_line(shell, &command) {
+        println!("PROMPT_COMMAND: {}", e);
+    }
+    shell.last_status = status;
+}
 
-    for (idx, line) in lines.iter().enumerate() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') { continue; }
+/// ------------------- COMMAND FIFO -------------------
+/// Commands fed through the named pipe in `FALSH_CMD_FIFO`, one per line.
+/// Lines are run as if typed at the prompt; a line may arrive in several
+/// writes, so partial input waits in `pending` until its newline.
+#[cfg(unix)]
+struct CmdFifo {
+    file: File,
+    pending: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl CmdFifo {
+    /// Opens `FALSH_CMD_FIFO` if it names an existing FIFO. It is opened
+    /// read-write so the shell never sees end-of-file when a writer leaves.
+    fn open() -> Option<CmdFifo> {
+        use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+        let path = env::var_os("FALSH_CMD_FIFO").filter(|p| !p.is_empty())?;
+        match fs::metadata(&path) {
+            Ok(meta) if meta.file_type().is_fifo() => {}
+            _ => {
+                println!("Warning: FALSH_CMD_FIFO '{}' is not a named pipe; ignoring it.", Path::new(&path).display());
+                return None;
+            }
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .map_err(|e| println!("Warning: cannot open FALSH_CMD_FIFO '{}': {}", Path::new(&path).display(), e))
+            .ok()?;
+        Some(CmdFifo { file, pending: Vec::new() })
+    }
+
+    /// Waits until the user starts typing or the FIFO has complete lines,
+    /// returning those lines (empty when the terminal is ready instead).
+    fn wait(&mut self) -> Vec<String> {
+        use std::os::unix::io::AsRawFd;
+        // Raw mode lets a single keypress wake the poll instead of a whole line.
+        let raw = crossterm::terminal::enable_raw_mode().is_ok();
+        let mut fds = [
+            libc::pollfd { fd: 0, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        let lines = loop {
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+            if ready < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted { continue; }
+            if ready < 0 || fds[0].revents != 0 { break Vec::new(); }
+            let lines = self.read_lines();
+            if !lines.is_empty() { break lines; }
+        };
+        if raw { let _ = crossterm::terminal::disable_raw_mode(); }
+        lines
+    }
 
-        if let Err(e) = execute_line(trimmed) {
-            println!("Error executing line {} in ~/.falshrc: '{}': {}", idx + 1, trimmed, e);
+    fn read_lines(&mut self) -> Vec<String> {
+        use std::io::Read;
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = self.file.read(&mut buf) {
+            if n == 0 { break; }
+            self.pending.extend_from_slice(&buf[..n]);
         }
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else { return Vec::new() };
+        let complete: Vec<u8> = self.pending.drain(..=end).collect();
+        String::from_utf8_lossy(&complete)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
     }
 }
 
+/// Keeps reading lines with a `> ` prompt while `line` is unfinished, such
+/// as after a trailing `\` or with a here-document still waiting for its
+/// delimiter. End of input stops early and leaves the parser to report what
+/// is missing.
+fn read_continuation(rl: &mut Editor<FalshHelper, rustyline::history::DefaultHistory>, mut line: String) -> rustyline::Result<String> {
+    while parser::incomplete(&line) {
+        match rl.readline("> ") {
+            Ok(more) => {
+                line.push('\n');
+                line.push_str(&more);
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(line)
+}
+
+fn print_usage() {
+    println!("falsh {} - the Friendly And Light SHell", env!("CARGO_PKG_VERSION"));
+    println!();
+    println!("Usage:");
+    println!("  falsh                  start an interactive shell");
+    println!("  falsh -c CMD [ARGS]    run CMD and exit with its status");
+    println!("  falsh SCRIPT [ARGS]    run each line of SCRIPT");
+    println!("  falsh --parse-dump CMD print how CMD parses without running it");
+    println!("  falsh --norc           do not read falshrc at startup");
+    println!("  falsh --rcfile FILE    read FILE instead of falshrc");
+    println!("  falsh -o NAME, +o NAME turn an option on or off, as with set");
+    println!("  falsh --var NAME=VALUE set a shell variable that is not exported");
+    println!("  falsh --alias NAME=VAL define an alias for this run only");
+    println!("  falsh --version, -V    print the version");
+    println!("  falsh --help, -h       print this help");
+    println!();
+    println!("Environment:");
+    println!("  XDG_CONFIG_HOME            where falshrc, path and history live");
+    println!("  NO_COLOR                   disable colored output");
+    println!("  PAGER                      pager for long builtin output (default less)");
+    println!("  FALSH_CORRECT=1            suggest commands when one is not found");
+    println!("  FALSH_CDSPELL=1            let cd fix a one-letter typo in the last directory");
+    println!("  FALSH_FORCE_CHILD_COLOR=1  set CLICOLOR_FORCE/FORCE_COLOR for commands");
+    println!("  FALSH_SET_TITLE=1          show the cwd or running command in the title");
+    println!("  FALSH_CMD_FIFO=PATH        also run commands written to this named pipe");
+    println!("  FALSH_EVENT_FD=N           write JSON command events to descriptor N");
+}
+
 fn main() -> rustyline::Result<()> {
-    let mut path = env::var_os("PATH").unwrap_or_default();
+    // Rust ignores SIGPIPE, and ignored signals survive exec, so children
+    // like `yes` in `yes | head -1` would report "Broken pipe" themselves.
+    // A handler is reset on exec instead, while the shell's own writes
+    // still just fail with `BrokenPipe`.
+    #[cfg(unix)]
+    set_signal_action(libc::SIGPIPE, None);
+
+    let path = env::var_os("PATH").unwrap_or_default();
     let extra = "/bin";
 
     let mut paths = std::env::split_paths(&path).collect::<Vec<_>>();
@@ -342,6 +3911,100 @@ fn main() -> rustyline::Result<()> {
     let new_path = std::env::join_paths(paths).expect("join failed");
     unsafe { env::set_var("PATH", &new_path) };
     load_persistent_into_env();
+
+    let mut shell = Shell::default();
+    let mut cli_args: Vec<String> = env::args().skip(1).collect();
+    // Startup file: the default falshrc, another file, or none at all.
+    let mut rcfile = Some(get_falshrc_file());
+    let mut explicit_rc = false;
+    loop {
+        match cli_args.first().map(String::as_str) {
+            Some("--norc") => {
+                rcfile = None;
+                cli_args.remove(0);
+            }
+            Some("--rcfile") => {
+                if cli_args.len() < 2 {
+                    eprintln!("falsh: --rcfile: option requires an argument");
+                    std::process::exit(2);
+                }
+                rcfile = Some(PathBuf::from(cli_args.remove(1)));
+                explicit_rc = true;
+                cli_args.remove(0);
+            }
+            // What a subshell inherits from its parent; see `subshell_args`.
+            Some(flag @ ("-o" | "+o" | "--var" | "--alias")) => {
+                if cli_args.len() < 2 {
+                    eprintln!("falsh: {}: option requires an argument", flag);
+                    std::process::exit(2);
+                }
+                let result = match flag {
+                    "--var" => match cli_args[1].split_once('=') {
+                        Some((name, value)) if parser::is_name(name) => {
+                            shell.vars.insert(name.to_string(), value.to_string());
+                            Ok(0)
+                        }
+                        _ => Err(format!("--var: '{}': not a valid assignment", cli_args[1])),
+                    },
+                    "--alias" => define_alias(&mut shell, &cli_args[1]).map(|_| 0),
+                    _ => set_builtin(&mut shell, &cli_args[..2]),
+                };
+                if let Err(e) = result {
+                    eprintln!("falsh: {}", e);
+                    std::process::exit(2);
+                }
+                cli_args.drain(..2);
+            }
+            _ => break,
+        }
+    }
+    match cli_args.first().map(String::as_str) {
+        Some("--version" | "-V") => {
+            println!("{}", version_line());
+            return Ok(());
+        }
+        Some("--help" | "-h") => {
+            print_usage();
+            return Ok(());
+        }
+        Some("--parse-dump") => {
+            let Some(command) = cli_args.get(1) else {
+                eprintln!("falsh: --parse-dump: option requires an argument");
+                std::process::exit(2);
+            };
+            match parser::parse(command) {
+                Ok(list) => print!("{}", parser::dump(&list)),
+                Err(e) => {
+                    println!("{}", e);
+                    std::process::exit(2);
+                }
+            }
+            return Ok(());
+        }
+        Some("-c") => {
+            let Some(command) = cli_args.get(1) else {
+                eprintln!("falsh: -c: option requires an argument");
+                std::process::exit(2);
+            };
+            shell.positional = cli_args[2..].to_vec();
+            if let Err(e) = run_line(&mut shell, command) {
+                println!("{}", e);
+            }
+            run_trap(&mut shell, "EXIT");
+            std::process::exit(shell.last_status);
+        }
+        Some(script) => {
+            shell.positional = cli_args[1..].to_vec();
+            if let Err(e) = source_file(&mut shell, Path::new(script)) {
+                println!("{}", e);
+                std::process::exit(127);
+            }
+            run_trap(&mut shell, "EXIT");
+            std::process::exit(shell.last_status);
+        }
+        None => {}
+    }
+
     let plain = "Running in ";
     let text = "falsh";
 
@@ -359,46 +4022,201 @@ fn main() -> rustyline::Result<()> {
         let b = start_rgb.2 + (end_rgb.2 - start_rgb.2) * i / len;
 
         // Bold + Italic + RGB per character
-        gradient.push_str(&format!("\x1B[1;3;38;2;{};{};{}m{}\x1B[0m", r, g, b, c));
+        if color_enabled() {
+            gradient.push_str(&format!("\x1B[1;3;38;2;{};{};{}m{}\x1B[0m", r, g, b, c));
+        } else {
+            gradient.push(c);
+        }
     }
 
-    println!("{}{}", plain, gradient);    load_falshrc(); // load ~/.falshrc at startup
+    println!("{}{}", plain, gradient);
 
-    let builtins = vec![
-        "cd".to_string(),
-        "pwd".to_string(),
-        "addToPath".to_string(),
-        "listPaths".to_string(),
-        "exit".to_string(),
-        "export".to_string(),
-    ];
+    // Saved aliases first, so the rc file can override or remove them.
+    load_aliases(&mut shell);
+    if let Some(file) = rcfile { load_falshrc(&mut shell, &file, explicit_rc); }
+
+    let builtins = BUILTINS.iter().map(|b| b.to_string()).collect();
 
     let helper = FalshHelper {
-        file_comp: FilenameCompleter::new(),
         builtins,
+        completions: shell.completions.clone(),
     };
 
     let mut rl = Editor::<FalshHelper, rustyline::history::DefaultHistory>::new()?;
     rl.set_helper(Some(helper));
+    let history_file = get_history_file();
+    let _ = rl.load_history(&history_file);
+    shell.history = rl.history().iter().cloned().collect();
+    #[cfg(unix)]
+    let mut fifo = CmdFifo::open();
 
-    loop {
+    shell.interactive = true;
+    // Text to start the next prompt with, for `histverify`.
+    let mut initial = String::new();
+    'repl: loop {
+        report_jobs(&mut shell);
+        run_prompt_command(&mut shell);
         let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("?"));
-        let prompt = format!("{}> ", cwd.display());
-        let readline = rl.readline(&prompt);
+        set_title(&cwd.display().to_string());
+        let prompt = format!("{}{} ", cwd.display(), prompt_marker(shell.last_status));
+        #[cfg(unix)]
+        if let Some(fifo) = fifo.as_mut() {
+            print!("{}", prompt);
+            let _ = io::stdout().flush();
+            let lines = fifo.wait();
+            if !lines.is_empty() {
+                println!();
+                for line in lines {
+                    let line = match expand_history(&shell.history, &line) {
+                        Ok(line) => line,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
+                    if line == "exit" { break 'repl; }
+                    println!("{}", line);
+                    let _ = rl.add_history_entry(line.as_str());
+                    set_command_title(&shell, &line);
+                    if let Err(e) = run_line(&mut shell, &line) {
+                        println!("{}", e);
+                    }
+                    shell.history.push(line);
+                    if shell.errexit_stops(shell.last_status) { break 'repl; }
+                }
+                continue;
+            }
+            // The user is typing: let the editor redraw the prompt in place.
+            print!("\r");
+        }
+        let readline = rl.readline_with_initial(&prompt, (&std::mem::take(&mut initial), ""))
+            .and_then(|line| read_continuation(&mut rl, line));
         let input = match readline {
-            Ok(line) => { let _ = rl.add_history_entry(line.as_str()); line.trim().to_string() },
-            Err(ReadlineError::Interrupted) => { println!("^C"); continue; },
+            Ok(line) => line.trim().to_string(),
+            Err(ReadlineError::Interrupted) => { println!("^C"); run_trap(&mut shell, "INT"); continue; },
             Err(ReadlineError::Eof) => break,
             Err(err) => { println!("Error: {:?}", err); break; }
         };
+        // Like bash, the history keeps the expanded line.
+        let input = match expand_history(&shell.history, &input) {
+            Ok(expanded) if expanded != input && shell.options.histverify => {
+                initial = expanded;
+                continue;
+            }
+            Ok(expanded) => {
+                if expanded != input { println!("{}", expanded); }
+                expanded
+            }
+            Err(e) => {
+                let _ = rl.add_history_entry(input.as_str());
+                println!("{}", e);
+                continue;
+            }
+        };
+        let _ = rl.add_history_entry(input.as_str());
 
         if input.is_empty() { continue; }
         if input == "exit" { break; }
 
-        if let Err(e) = execute_line(&input) {
+        set_command_title(&shell, &input);
+        if let Err(e) = run_line(&mut shell, &input) {
             println!("{}", e);
         }
+        shell.history.push(input);
+        if shell.errexit_stops(shell.last_status) { break; }
     }
 
+    run_trap(&mut shell, "EXIT");
+    if let Some(parent) = history_file.parent() { fs::create_dir_all(parent).ok(); }
+    if let Err(e) = rl.save_history(&history_file) {
+        println!("Failed to save history to {}: {}", history_file.display(), e);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Held by tests that change the working directory, which all tests
+    /// in the process share.
+    fn lock_cwd() -> MutexGuard<'static, ()> {
+        static CWD: Mutex<()> = Mutex::new(());
+        CWD.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn shell_with_aliases(aliases: &[(&str, &str)]) -> Shell {
+        let mut shell = Shell::default();
+        for (name, value) in aliases {
+            shell.aliases.insert(name.to_string(), value.to_string());
+        }
+        shell
+    }
+
+    /// Asserts that `input` parses to the same tree as `expected` does.
+    fn assert_expands(shell: &Shell, input: &str, expected: &str) {
+        let list = parse_line(shell, input).unwrap();
+        assert_eq!(parser::dump(&list), parser::dump(&parser::parse(expected).unwrap()), "{}", input);
+    }
+
+    #[test]
+    fn alias_expands_first_word_of_each_command() {
+        let shell = shell_with_aliases(&[("ll", "ls -l")]);
+        assert_expands(&shell, "ll /tmp; ll | ll", "ls -l /tmp; ls -l | ls -l");
+        assert_expands(&shell, "echo ll", "echo ll");
+    }
+
+    #[test]
+    fn alias_value_ending_in_blank_checks_next_word() {
+        let shell = shell_with_aliases(&[("s", "sudo "), ("ll", "ls -l")]);
+        assert_expands(&shell, "s ll", "sudo ls -l");
+        assert_expands(&shell, "s s ll", "sudo sudo ls -l");
+    }
+
+    #[test]
+    fn alias_never_expands_itself_again() {
+        let shell = shell_with_aliases(&[("a", "echo x; a"), ("p", "echo x | p"), ("b", "c"), ("c", "b")]);
+        assert_expands(&shell, "a", "echo x; a");
+        assert_expands(&shell, "p", "echo x | p");
+        assert_expands(&shell, "b; c", "b; c");
+        // A later command outside the expansion still gets its alias.
+        assert_expands(&shell, "a; a", "echo x; a; echo x; a");
+        let shell = shell_with_aliases(&[("a", "a; a")]);
+        assert_expands(&shell, "a", "a; a");
+    }
+
+    #[test]
+    fn alias_expansions_are_capped() {
+        let shell = shell_with_aliases(&[("x", "echo; echo; echo")]);
+        let line = vec!["x"; MAX_ALIAS_EXPANSIONS + 1].join("; ");
+        assert!(parse_line(&shell, &line).is_err());
+    }
+
+    #[test]
+    fn completion_follows_cd() {
+        let dir = env::temp_dir().join(format!("falsh-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("beta")).unwrap();
+        File::create(dir.join("alpha.txt")).unwrap();
+        let _cwd = lock_cwd();
+        let old = env::current_dir().unwrap();
+        let mut shell = Shell::default();
+        let cd = ["cd".to_string(), dir.display().to_string()];
+        run_builtin(&mut shell, &cd).unwrap();
+
+        let helper = FalshHelper { builtins: Vec::new(), completions: Completions::default() };
+        let history = rustyline::history::DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let complete = |line: &str| {
+            let (start, pairs) = helper.complete(line, line.len(), &ctx).unwrap();
+            (start, pairs.into_iter().map(|pair| pair.replacement).collect::<Vec<_>>())
+        };
+        let bare = complete("cat ");
+        let partial = complete("cat al");
+
+        env::set_current_dir(old).unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(bare, (4, vec!["alpha.txt".to_string(), "beta/".to_string()]));
+        assert_eq!(partial, (4, vec!["alpha.txt".to_string()]));
+    }
+}
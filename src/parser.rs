@@ -0,0 +1,1039 @@
+//! Lexer and parser for command lines.
+//!
+//! A line is cut into words and operators (`;`, `&`, `&&`, `||`, `|`, `|&`,
+//! `(`, `)`, `<`, `<>`, `>`, `>|`, `>>`, `<&`, `>&`, `<<`, `<<<`, the
+//! redirections optionally led by a descriptor number as in `3<` or `2>&1`;
+//! none need spaces around them, so `ls>out.txt` and `a|b` work), then parsed
+//! into a `List` of `Pipeline`s of `SimpleCommand`s, where `( list )` (a
+//! subshell) and `{ list; }` (a group) may stand in for a command. Words
+//! remember how each piece was quoted so expansion can tell `"*"` from `*`,
+//! and `$( ... )`, `$(( ... ))`, `${ ... }`, `` `...` ``, `<( ... )` and
+//! `>( ... )` always stay inside the word they start in.
+//!
+//! A line may hold several lines of input: newlines separate commands like
+//! `;`, a backslash before a newline joins the two lines, and the body of a
+//! here-document (`<<EOF`, or `<<-EOF` to drop leading tabs) is read from
+//! the lines after the one its operator is on.
+//!
+//! A `#` at the start of a word begins a comment that runs to the end of
+//! the line, so `echo a#b` and `echo '#'` keep theirs.
+//!
+//! A backslash quotes the next character outside quotes; inside double
+//! quotes it does so only before `$`, `` ` ``, `"`, `\` and a newline, as in
+//! POSIX sh. Escaped characters become `Single` parts so nothing expands them.
+//! `$'...'` is a single-quoted string in which C escapes like `\n`, `\t`,
+//! `\x1b` and `\u00e9` are decoded.
+
+/// A piece of a word, by how it was quoted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text: expanded, split on whitespace and globbed.
+    Bare(String),
+    /// `'...'`: taken literally.
+    Single(String),
+    /// `"..."`: expanded, but never split or globbed.
+    Double(String),
+    /// `<(command)`, or `>(command)` when `output` is set: replaced by a
+    /// path through which the command's output can be read (or its input
+    /// written).
+    Process { command: String, output: bool },
+}
+
+/// A shell word with its byte span in the line it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Word {
+    /// The word's text when it is a single unquoted piece with nothing to
+    /// expand, the only form an alias name is recognized in.
+    pub fn literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Bare(text)] if !text.contains(['$', '`']) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The text with quotes removed and nothing expanded.
+    pub fn unquoted(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                WordPart::Bare(text) | WordPart::Single(text) | WordPart::Double(text) => text.clone(),
+                WordPart::Process { command, output } => format!("{}({})", if *output { '>' } else { '<' }, command),
+            })
+            .collect()
+    }
+
+    /// Splits an unquoted `NAME=` prefix off the word, giving the name and
+    /// the value still to be expanded.
+    pub fn assignment(&self) -> Option<(String, Word)> {
+        let Some(WordPart::Bare(first)) = self.parts.first() else { return None };
+        let (name, rest) = first.split_once('=')?;
+        if !is_name(name) {
+            return None;
+        }
+        let mut parts = Vec::new();
+        if !rest.is_empty() {
+            parts.push(WordPart::Bare(rest.to_string()));
+        }
+        parts.extend(self.parts[1..].iter().cloned());
+        Some((name.to_string(), Word { parts, start: self.start + name.len() + 1, end: self.end }))
+    }
+}
+
+/// Whether `name` is a valid variable name: a letter or `_`, then letters,
+/// digits and `_`.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RedirectOp {
+    /// `<`
+    Read,
+    /// `<>`
+    ReadWrite,
+    /// `>`
+    Write,
+    /// `>|`: like `>`, but overwrites even under `noclobber`.
+    Clobber,
+    /// `>>`
+    Append,
+    /// `<&`: the target names a descriptor to copy, or `-` to close.
+    DupIn,
+    /// `>&`: likewise, for output.
+    DupOut,
+    /// `<<` and `<<-`: the target is the here-document's body.
+    HereDoc,
+    /// `<<<`: the target, plus a newline, is the input.
+    HereString,
+}
+
+impl RedirectOp {
+    /// The operator as written, without a descriptor number.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            RedirectOp::Read => "<",
+            RedirectOp::ReadWrite => "<>",
+            RedirectOp::Write => ">",
+            RedirectOp::Clobber => ">|",
+            RedirectOp::Append => ">>",
+            RedirectOp::DupIn => "<&",
+            RedirectOp::DupOut => ">&",
+            RedirectOp::HereDoc => "<<",
+            RedirectOp::HereString => "<<<",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub op: RedirectOp,
+    pub target: Word,
+    /// How many of the command's words came before it.
+    pub position: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    /// The text inside `( ... )`, for a subshell, which has no words.
+    pub subshell: Option<String>,
+    /// The list inside `{ ...; }`, for a group, which has no words either.
+    pub group: Option<Group>,
+    /// The command as written, for error messages.
+    pub text: String,
+}
+
+/// `{ list; }`: commands run in the current shell as one unit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub list: List,
+    /// The text between the braces, to run it as a subshell inside a
+    /// pipeline.
+    pub text: String,
+}
+
+/// Commands joined by `|`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+    /// Ended by `&`: runs without the shell waiting for it.
+    pub background: bool,
+}
+
+/// How a pipeline is joined to the one before it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connector {
+    /// `;`, or the start of the line: always runs.
+    Seq,
+    /// `&&`: runs if the previous status was 0.
+    And,
+    /// `||`: runs if the previous status was not 0.
+    Or,
+}
+
+/// Pipelines joined by `;`, `&&` and `||`, run left to right. An empty line
+/// gives no pipelines.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct List {
+    pub items: Vec<(Connector, Pipeline)>,
+}
+
+impl List {
+    /// Every command in the list, those inside `{ ...; }` groups included,
+    /// in the order they appear.
+    pub fn commands(&self) -> Box<dyn Iterator<Item = &SimpleCommand> + '_> {
+        Box::new(self.items.iter().flat_map(|(_, pipeline)| &pipeline.commands).flat_map(|command| {
+            std::iter::once(command).chain(command.group.iter().flat_map(|group| group.list.commands()))
+        }))
+    }
+}
+
+/// Parses one line, which may span several lines of input.
+pub fn parse(line: &str) -> Result<List, String> {
+    let (tokens, pending) = tokenize(line)?;
+    if let Some(heredoc) = pending.first() {
+        return Err(format!("Syntax error: here-document wanted '{}' before the end of input", heredoc.delimiter));
+    }
+    let mut parser = Parser { line, tokens, pos: 0 };
+    let list = parser.list()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.unexpected());
+    }
+    Ok(list)
+}
+
+/// An indented outline of `list` for `--parse-dump` and `set -n`: one
+/// line per pipeline, command, word and redirection, with each word's
+/// parts shown by how they were quoted.
+pub fn dump(list: &List) -> String {
+    let mut out = String::new();
+    dump_list(list, 0, &mut out);
+    out
+}
+
+fn dump_list(list: &List, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for (connector, pipeline) in &list.items {
+        let connector = match connector {
+            Connector::Seq => "",
+            Connector::And => "&& ",
+            Connector::Or => "|| ",
+        };
+        let background = if pipeline.background { " &" } else { "" };
+        out.push_str(&format!("{}{}pipeline{}\n", indent, connector, background));
+        for command in &pipeline.commands {
+            out.push_str(&format!("{}  command\n", indent));
+            if let Some(text) = &command.subshell {
+                out.push_str(&format!("{}    subshell {:?}\n", indent, text));
+            }
+            if let Some(group) = &command.group {
+                out.push_str(&format!("{}    group\n", indent));
+                dump_list(&group.list, depth + 3, out);
+            }
+            for word in &command.words {
+                out.push_str(&format!("{}    word {}\n", indent, dump_word(word)));
+            }
+            for redirect in &command.redirects {
+                let target = dump_word(&redirect.target);
+                out.push_str(&format!("{}    redirect {}{} {}\n", indent, redirect.fd, redirect.op.symbol(), target));
+            }
+        }
+    }
+}
+
+fn dump_word(word: &Word) -> String {
+    word.parts.iter().map(|part| format!("{:?}", part)).collect::<Vec<_>>().join(" ")
+}
+
+/// Where byte `offset` of `line` is, for error messages: `at column 14`,
+/// or `at line 2, column 3` past the first line. Columns count characters
+/// from 1.
+fn location(line: &str, offset: usize) -> String {
+    let before = &line[..offset];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    let column = before[line_start..].chars().count() + 1;
+    match before.matches('\n').count() {
+        0 => format!("at column {}", column),
+        newlines => format!("at line {}, column {}", newlines + 1, column),
+    }
+}
+
+/// Whether `line` needs more lines of input before it can run: a quote,
+/// `(`, `{`, `$(`, `${` or backquote is still open, the line ends in a backslash or
+/// in `|`, `|&`, `&&` or `||`, or a here-document has not reached its
+/// delimiter yet.
+pub fn incomplete(line: &str) -> bool {
+    let (tokens, pending) = match tokenize(line) {
+        Ok(lexed) => lexed,
+        Err(e) => return e.starts_with("Syntax error: unterminated"),
+    };
+    // The backslash must end the last token, not sit in a comment.
+    let continued = (line.len() - line.trim_end_matches('\\').len()) % 2 == 1
+        && tokens.last().is_some_and(|t| t.end == line.len());
+    let operator = tokens
+        .iter()
+        .rev()
+        .find(|t| t.token != Token::Newline)
+        .is_some_and(|t| matches!(t.token, Token::Pipe | Token::PipeBoth | Token::AndIf | Token::OrIf));
+    let unclosed = || {
+        matches!(parse(line), Err(e) if e.starts_with("Syntax error: expected ')'") || e.starts_with("Syntax error: expected '}'"))
+    };
+    continued || operator || !pending.is_empty() || unclosed()
+}
+
+struct Parser<'a> {
+    line: &'a str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    /// Source text of token `idx`.
+    fn text(&self, idx: usize) -> &str {
+        let token = &self.tokens[idx];
+        &self.line[token.start..token.end]
+    }
+
+    /// Where token `idx` starts, as `location` puts it.
+    fn at(&self, idx: usize) -> String {
+        location(self.line, self.tokens[idx].start)
+    }
+
+    /// The error for a token that cannot go where it is.
+    fn unexpected(&self) -> String {
+        match self.tokens.get(self.pos) {
+            Some(Spanned { token: Token::Newline, .. }) => format!("Syntax error near newline {}", self.at(self.pos)),
+            Some(_) => format!("Syntax error near '{}' {}", self.text(self.pos), self.at(self.pos)),
+            None => "Syntax error: unexpected end of input".into(),
+        }
+    }
+
+    /// The error for an operator with no command after it.
+    fn missing_command(&self, op: usize) -> String {
+        format!("Syntax error: expected a command after '{}' {}", self.text(op), self.at(op))
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    /// Whether the next token closes a list: the end of input, `)`, or a
+    /// `}` word where a command would start.
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None | Some(Token::RParen) => true,
+            Some(Token::Word(word)) => word.literal() == Some("}"),
+            _ => false,
+        }
+    }
+
+    fn list(&mut self) -> Result<List, String> {
+        let mut list = List::default();
+        let mut connector = Connector::Seq;
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() { break; }
+            let mut pipeline = self.pipeline(None)?;
+            pipeline.background = self.peek() == Some(&Token::Amp);
+            list.items.push((connector, pipeline));
+            connector = match self.peek() {
+                None | Some(Token::RParen) => break,
+                Some(Token::Semi | Token::Amp | Token::Newline) => Connector::Seq,
+                Some(Token::AndIf) => Connector::And,
+                Some(Token::OrIf) => Connector::Or,
+                _ => return Err(self.unexpected()),
+            };
+            let op = self.pos;
+            self.pos += 1;
+            if connector != Connector::Seq {
+                self.skip_newlines();
+                if self.peek().is_none() {
+                    return Err(self.missing_command(op));
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    /// Commands joined by `|`. `after` is the operator before the first one,
+    /// for the error when it is missing.
+    fn pipeline(&mut self, after: Option<usize>) -> Result<Pipeline, String> {
+        let mut pipeline = Pipeline::default();
+        let mut after = after;
+        loop {
+            let mut command = self.command(after)?;
+            match self.peek() {
+                Some(Token::Pipe) => {}
+                // `a |& b` is `a 2>&1 | b`.
+                Some(Token::PipeBoth) => {
+                    let Spanned { start, end, .. } = self.tokens[self.pos];
+                    let target = Word { parts: vec![WordPart::Bare("1".into())], start, end };
+                    command.redirects.push(Redirect { fd: 2, op: RedirectOp::DupOut, target, position: command.words.len() });
+                }
+                _ => {
+                    pipeline.commands.push(command);
+                    return Ok(pipeline);
+                }
+            }
+            pipeline.commands.push(command);
+            after = Some(self.pos);
+            self.pos += 1;
+            self.skip_newlines();
+        }
+    }
+
+    fn command(&mut self, after: Option<usize>) -> Result<SimpleCommand, String> {
+        let mut command = SimpleCommand::default();
+        let first = self.pos;
+        match self.peek() {
+            Some(Token::LParen) => command.subshell = Some(self.subshell()?),
+            Some(Token::Word(word)) if word.literal() == Some("{") => command.group = Some(self.group()?),
+            _ => {}
+        }
+        let compound = self.pos > first;
+        while let Some(token) = self.tokens.get(self.pos) {
+            match &token.token {
+                // Only redirections may follow `( ... )` and `{ ...; }`.
+                Token::Word(_) if compound => return Err(self.unexpected()),
+                Token::Word(word) => command.words.push(word.clone()),
+                Token::Redirect(fd, op) => {
+                    let (fd, op) = (*fd, *op);
+                    let Some(Token::Word(target)) = self.tokens.get(self.pos + 1).map(|t| &t.token) else {
+                        return Err(format!("Syntax error: '{}' requires a filename {}", self.text(self.pos), self.at(self.pos)));
+                    };
+                    command.redirects.push(Redirect { fd, op, target: target.clone(), position: command.words.len() });
+                    self.pos += 1;
+                }
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if self.pos == first {
+            return Err(match (self.peek(), after) {
+                (None | Some(Token::Semi | Token::Amp | Token::Newline), Some(op)) => self.missing_command(op),
+                _ => self.unexpected(),
+            });
+        }
+        command.text = self.line[self.tokens[first].start..self.tokens[self.pos - 1].end].to_string();
+        Ok(command)
+    }
+
+    /// `( list )`, returning the text between the parentheses.
+    fn subshell(&mut self) -> Result<String, String> {
+        let open = self.pos;
+        self.pos += 1;
+        let list = self.list()?;
+        if self.peek() != Some(&Token::RParen) {
+            return Err(format!("Syntax error: expected ')' to close the '(' {}", self.at(open)));
+        }
+        if list.items.is_empty() {
+            return Err(self.unexpected());
+        }
+        let text = self.line[self.tokens[open].end..self.tokens[self.pos].start].to_string();
+        self.pos += 1;
+        Ok(text)
+    }
+
+    /// `{ list; }`. The closing `}` is only recognized where a command
+    /// could start, so `{ echo }` is still waiting for it.
+    fn group(&mut self) -> Result<Group, String> {
+        let open = self.pos;
+        self.pos += 1;
+        let list = self.list()?;
+        if !matches!(self.peek(), Some(Token::Word(word)) if word.literal() == Some("}")) {
+            return Err(format!("Syntax error: expected '}}' to close the '{{' {}", self.at(open)));
+        }
+        if list.items.is_empty() {
+            return Err(self.unexpected());
+        }
+        let text = self.line[self.tokens[open].end..self.tokens[self.pos].start].to_string();
+        self.pos += 1;
+        Ok(Group { list, text })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(Word),
+    Pipe,
+    /// `|&`
+    PipeBoth,
+    Semi,
+    /// `&`
+    Amp,
+    Newline,
+    LParen,
+    RParen,
+    AndIf,
+    OrIf,
+    Redirect(i32, RedirectOp),
+}
+
+struct Spanned {
+    token: Token,
+    start: usize,
+    end: usize,
+}
+
+/// Characters that end an unquoted word.
+fn is_meta(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '|' | ';' | '&' | '<' | '>' | ')')
+}
+
+/// A here-document whose body has not been read yet.
+struct PendingHereDoc {
+    /// Index of the delimiter word's token, which the body replaces.
+    token: usize,
+    delimiter: String,
+    /// `<<-`: leading tabs are dropped from the body and delimiter lines.
+    strip_tabs: bool,
+    /// A quoted delimiter leaves the body unexpanded.
+    quoted: bool,
+}
+
+/// Splits `line` into tokens, also returning the here-documents still
+/// waiting for their delimiter when the input ran out.
+fn tokenize(line: &str) -> Result<(Vec<Spanned>, Vec<PendingHereDoc>), String> {
+    let mut tokens: Vec<Spanned> = Vec::new();
+    let mut pending = Vec::new();
+    let mut pos = 0;
+    while let Some(c) = line[pos..].chars().next() {
+        if c == '\n' {
+            tokens.push(Spanned { token: Token::Newline, start: pos, end: pos + 1 });
+            pos = read_heredocs(line, pos + 1, &mut pending, &mut tokens);
+            continue;
+        }
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        // A `#` where a word could start comments out the rest of the line.
+        if c == '#' {
+            pos = line[pos..].find('\n').map_or(line.len(), |end| pos + end);
+            continue;
+        }
+        let start = pos;
+        let token = if line[pos..].starts_with("&&") {
+            pos += 2;
+            Token::AndIf
+        } else if line[pos..].starts_with("||") {
+            pos += 2;
+            Token::OrIf
+        } else if line[pos..].starts_with("|&") {
+            pos += 2;
+            Token::PipeBoth
+        } else if c == '|' {
+            pos += 1;
+            Token::Pipe
+        } else if c == ';' {
+            pos += 1;
+            Token::Semi
+        } else if c == '&' {
+            pos += 1;
+            Token::Amp
+        } else if c == '(' {
+            pos += 1;
+            Token::LParen
+        } else if c == ')' {
+            pos += 1;
+            Token::RParen
+        } else if let Some((fd, op, len)) = redirect_op(&line[pos..]) {
+            pos += len;
+            Token::Redirect(fd, op)
+        } else {
+            let (word, end) = read_word(line, pos)?;
+            pos = end;
+            if let Some(Spanned { token: Token::Redirect(_, RedirectOp::HereDoc), start: op_start, end: op_end }) = tokens.last() {
+                pending.push(PendingHereDoc {
+                    token: tokens.len(),
+                    delimiter: word.unquoted(),
+                    strip_tabs: line[*op_start..*op_end].ends_with('-'),
+                    quoted: word.parts.iter().any(|part| !matches!(part, WordPart::Bare(_))),
+                });
+            }
+            Token::Word(word)
+        };
+        tokens.push(Spanned { token, start, end: pos });
+    }
+    Ok((tokens, pending))
+}
+
+/// Reads the bodies of the pending here-documents from the lines starting
+/// at `pos`, in order, returning where the next command starts. Any that
+/// run out of input stay pending.
+fn read_heredocs(line: &str, mut pos: usize, pending: &mut Vec<PendingHereDoc>, tokens: &mut [Spanned]) -> usize {
+    while let Some(heredoc) = pending.first() {
+        let mut body = String::new();
+        let mut found = false;
+        while pos < line.len() {
+            let end = line[pos..].find('\n').map_or(line.len(), |i| pos + i);
+            let mut text = &line[pos..end];
+            if heredoc.strip_tabs { text = text.trim_start_matches('\t'); }
+            pos = (end + 1).min(line.len());
+            if text == heredoc.delimiter {
+                found = true;
+                break;
+            }
+            body.push_str(text);
+            body.push('\n');
+        }
+        if !found { return pos; }
+        let heredoc = pending.remove(0);
+        let parts = if heredoc.quoted { vec![WordPart::Single(body)] } else { heredoc_parts(&body) };
+        if let Token::Word(word) = &mut tokens[heredoc.token].token {
+            word.parts = parts;
+        }
+    }
+    pos
+}
+
+/// An unquoted here-document body: expanded like double-quoted text, with
+/// `\$`, `` \` `` and `\\` escaped and `\` before a newline joining lines.
+fn heredoc_parts(body: &str) -> Vec<WordPart> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().copied()) {
+            ('\\', Some(escaped @ ('$' | '`' | '\\'))) => {
+                chars.next();
+                if !text.is_empty() { parts.push(WordPart::Double(std::mem::take(&mut text))); }
+                parts.push(WordPart::Single(escaped.to_string()));
+            }
+            ('\\', Some('\n')) => { chars.next(); }
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() || parts.is_empty() { parts.push(WordPart::Double(text)); }
+    parts
+}
+
+/// A redirection operator at the start of `rest`, with its length. Digits
+/// count as a descriptor only when the operator follows them directly.
+fn redirect_op(rest: &str) -> Option<(i32, RedirectOp, usize)> {
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (fd, op_text) = rest.split_at(digits);
+    // `<(cmd)` and `>(cmd)` are process substitutions, not redirections.
+    if op_text.get(1..).is_some_and(|after| after.starts_with('(')) {
+        return None;
+    }
+    let (default_fd, op, len) = if op_text.starts_with("<<<") {
+        (0, RedirectOp::HereString, 3)
+    } else if op_text.starts_with("<<-") {
+        (0, RedirectOp::HereDoc, 3)
+    } else if op_text.starts_with("<<") {
+        (0, RedirectOp::HereDoc, 2)
+    } else if op_text.starts_with("<&") {
+        (0, RedirectOp::DupIn, 2)
+    } else if op_text.starts_with(">&") {
+        (1, RedirectOp::DupOut, 2)
+    } else if op_text.starts_with("<>") {
+        (0, RedirectOp::ReadWrite, 2)
+    } else if op_text.starts_with('<') {
+        (0, RedirectOp::Read, 1)
+    } else if op_text.starts_with(">|") {
+        (1, RedirectOp::Clobber, 2)
+    } else if op_text.starts_with(">>") {
+        (1, RedirectOp::Append, 2)
+    } else if op_text.starts_with('>') {
+        (1, RedirectOp::Write, 1)
+    } else {
+        return None;
+    };
+    let fd = if fd.is_empty() { default_fd } else { fd.parse().ok()? };
+    Some((fd, op, digits + len))
+}
+
+/// Reads the word starting at `start`, returning it and the offset just
+/// past it.
+fn read_word(line: &str, start: usize) -> Result<(Word, usize), String> {
+    let mut parts = Vec::new();
+    let mut bare = String::new();
+    let mut pos = start;
+    let at = |e: String, pos: usize| format!("{} {}", e, location(line, pos));
+    while let Some(c) = line[pos..].chars().next() {
+        match c {
+            '<' | '>' if line[pos + 1..].starts_with('(') => {
+                flush_bare(&mut bare, &mut parts);
+                let end = match_bracket(line, pos + 1).ok_or_else(|| at(format!("Syntax error: unterminated '{}('", c), pos))?;
+                parts.push(WordPart::Process { command: line[pos + 2..end - 1].to_string(), output: c == '>' });
+                pos = end;
+            }
+            c if is_meta(c) => break,
+            // Mid-word parentheses stay in the word, for glob qualifiers
+            // like `*(.)`.
+            '(' if pos > start => {
+                let end = match_bracket(line, pos).ok_or_else(|| at("Syntax error: unterminated '('".into(), pos))?;
+                bare.push_str(&line[pos..end]);
+                pos = end;
+            }
+            '\'' => {
+                flush_bare(&mut bare, &mut parts);
+                let close = line[pos + 1..].find('\'').ok_or_else(|| at("Syntax error: unterminated quote".into(), pos))?;
+                parts.push(WordPart::Single(line[pos + 1..pos + 1 + close].to_string()));
+                pos += close + 2;
+            }
+            '"' => {
+                flush_bare(&mut bare, &mut parts);
+                pos = read_double(line, pos + 1, &mut parts).map_err(|e| at(e, pos))?;
+            }
+            '\\' => {
+                flush_bare(&mut bare, &mut parts);
+                match line[pos + 1..].chars().next() {
+                    Some('\n') => {}
+                    Some(escaped) => parts.push(WordPart::Single(escaped.to_string())),
+                    None => parts.push(WordPart::Single("\\".into())),
+                }
+                pos += 1 + line[pos + 1..].chars().next().map_or(0, char::len_utf8);
+            }
+            '$' if line[pos + 1..].starts_with('\'') => {
+                flush_bare(&mut bare, &mut parts);
+                let (text, end) = read_ansi_c(line, pos + 2).map_err(|e| at(e, pos))?;
+                parts.push(WordPart::Single(text));
+                pos = end;
+            }
+            '$' | '`' => {
+                let end = if c == '$' { scan_dollar(line, pos) } else { scan_backquote(line, pos) };
+                let end = end.map_err(|e| at(e, pos))?;
+                bare.push_str(&line[pos..end]);
+                pos = end;
+            }
+            _ => {
+                bare.push(c);
+                pos += c.len_utf8();
+            }
+        }
+    }
+    flush_bare(&mut bare, &mut parts);
+    Ok((Word { parts, start, end: pos }, pos))
+}
+
+fn flush_bare(bare: &mut String, parts: &mut Vec<WordPart>) {
+    if !bare.is_empty() {
+        parts.push(WordPart::Bare(std::mem::take(bare)));
+    }
+}
+
+/// Reads the inside of `$'...'` from just after the opening quote,
+/// returning the text with its escapes decoded and the offset past the
+/// closing quote.
+fn read_ansi_c(line: &str, start: usize) -> Result<(String, usize), String> {
+    let mut text = String::new();
+    let mut chars = line[start..].char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\'' => return Ok((text, start + idx + 1)),
+            '\\' => {
+                let Some((_, escape)) = chars.next() else { break };
+                match escape {
+                    'a' => text.push('\x07'),
+                    'b' => text.push('\x08'),
+                    'e' | 'E' => text.push('\x1b'),
+                    'f' => text.push('\x0c'),
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'v' => text.push('\x0b'),
+                    '0'..='7' => {
+                        let mut value = escape.to_digit(8).unwrap_or(0);
+                        for _ in 0..2 {
+                            match chars.clone().next().and_then(|(_, c)| c.to_digit(8)) {
+                                Some(digit) => {
+                                    value = value * 8 + digit;
+                                    chars.next();
+                                }
+                                None => break,
+                            }
+                        }
+                        text.extend(char::from_u32(value & 0xff));
+                    }
+                    'x' | 'u' | 'U' => {
+                        let max = match escape { 'x' => 2, 'u' => 4, _ => 8 };
+                        match take_hex(&mut chars, max) {
+                            Some(value) => text.extend(char::from_u32(value)),
+                            None => text.extend(['\\', escape]),
+                        }
+                    }
+                    'c' => {
+                        if let Some((_, control)) = chars.next() {
+                            text.extend(char::from_u32(control.to_ascii_uppercase() as u32 ^ 0x40));
+                        }
+                    }
+                    '\\' | '\'' | '"' | '?' => text.push(escape),
+                    other => text.extend(['\\', other]),
+                }
+            }
+            c => text.push(c),
+        }
+    }
+    Err("Syntax error: unterminated quote".into())
+}
+
+/// Consumes up to `max` hex digits from `chars`, returning their value.
+fn take_hex(chars: &mut std::str::CharIndices<'_>, max: usize) -> Option<u32> {
+    let rest = chars.as_str();
+    let len = rest.chars().take(max).take_while(char::is_ascii_hexdigit).count();
+    let value = u32::from_str_radix(&rest[..len], 16).ok()?;
+    chars.nth(len - 1);
+    Some(value)
+}
+
+/// Reads the inside of `"..."` from just after the opening quote into
+/// `parts`, returning the offset past the closing quote.
+fn read_double(line: &str, start: usize, parts: &mut Vec<WordPart>) -> Result<usize, String> {
+    let first = parts.len();
+    let mut text = String::new();
+    let mut pos = start;
+    while let Some(c) = line[pos..].chars().next() {
+        match c {
+            '"' => {
+                // `""` still makes an (empty) argument.
+                if !text.is_empty() || parts.len() == first {
+                    parts.push(WordPart::Double(text));
+                }
+                return Ok(pos + 1);
+            }
+            '$' | '`' => {
+                let end = if c == '$' { scan_dollar(line, pos)? } else { scan_backquote(line, pos)? };
+                text.push_str(&line[pos..end]);
+                pos = end;
+            }
+            '\\' => match line[pos + 1..].chars().next() {
+                Some(escaped @ ('$' | '`' | '"' | '\\')) => {
+                    if !text.is_empty() { parts.push(WordPart::Double(std::mem::take(&mut text))); }
+                    parts.push(WordPart::Single(escaped.to_string()));
+                    pos += 2;
+                }
+                Some('\n') => pos += 2,
+                _ => {
+                    text.push('\\');
+                    pos += 1;
+                }
+            },
+            _ => {
+                text.push(c);
+                pos += c.len_utf8();
+            }
+        }
+    }
+    Err("Syntax error: unterminated quote".into())
+}
+
+/// The offset just past a `$` expansion starting at `start`: the matching
+/// `)` of `$(` and `$((`, the `}` of `${`, or only the `$` itself otherwise.
+/// Quoted parentheses do not count.
+pub fn scan_dollar(line: &str, start: usize) -> Result<usize, String> {
+    let rest = &line[start + 1..];
+    match rest.chars().next() {
+        Some(open @ ('(' | '{')) => {
+            match_bracket(line, start + 1).ok_or_else(|| format!("Syntax error: unterminated '${}'", open))
+        }
+        _ => Ok(start + 1),
+    }
+}
+
+/// The offset just past the backquote closing the one at `start`, skipping
+/// escaped backquotes.
+pub fn scan_backquote(line: &str, start: usize) -> Result<usize, String> {
+    let mut escaped = false;
+    for (idx, c) in line[start + 1..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '`' => return Ok(start + 1 + idx + 1),
+            _ => {}
+        }
+    }
+    Err("Syntax error: unterminated '`'".into())
+}
+
+/// The offset just past the `)` or `}` matching the `(` or `{` at `open`.
+/// Quoted and escaped brackets do not count.
+fn match_bracket(line: &str, open: usize) -> Option<usize> {
+    let rest = &line[open..];
+    let (open_char, close_char) = if rest.starts_with('{') { ('{', '}') } else { ('(', ')') };
+    let mut depth = 0;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escaped = false;
+    for (idx, c) in rest.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if !in_single => escaped = true,
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            c if c == open_char && !in_single && !in_double => depth += 1,
+            c if c == close_char && !in_single && !in_double => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + idx + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bare(text: &str) -> WordPart {
+        WordPart::Bare(text.to_string())
+    }
+
+    fn single(text: &str) -> WordPart {
+        WordPart::Single(text.to_string())
+    }
+
+    fn double(text: &str) -> WordPart {
+        WordPart::Double(text.to_string())
+    }
+
+    /// The parts of each word of the only command in `line`.
+    fn words(line: &str) -> Vec<Vec<WordPart>> {
+        let list = parse(line).unwrap();
+        let [(_, pipeline)] = list.items.as_slice() else { panic!("{}: not one pipeline", line) };
+        let [command] = pipeline.commands.as_slice() else { panic!("{}: not one command", line) };
+        command.words.iter().map(|word| word.parts.clone()).collect()
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            words(r#"echo 'a b' "c $d" e\ f"#),
+            [vec![bare("echo")], vec![single("a b")], vec![double("c $d")], vec![bare("e"), single(" "), bare("f")]]
+        );
+        assert_eq!(words(r#"a"b"'c'"#), [vec![bare("a"), double("b"), single("c")]]);
+        assert_eq!(
+            words(r#""a \"b\" \$c \d""#),
+            [vec![double("a "), single("\""), double("b"), single("\""), double(" "), single("$"), double("c \\d")]]
+        );
+        assert_eq!(words(r"$'a\tb\x41'"), [vec![single("a\tbA")]]);
+        assert_eq!(words("''"), [vec![single("")]]);
+    }
+
+    #[test]
+    fn substitutions_stay_in_their_word() {
+        assert_eq!(words("echo $(ls 'x y') `a b` ${x:-a b}"), [
+            vec![bare("echo")],
+            vec![bare("$(ls 'x y')")],
+            vec![bare("`a b`")],
+            vec![bare("${x:-a b}")],
+        ]);
+        assert_eq!(words("echo $(( 1 + 2 ))"), [vec![bare("echo")], vec![bare("$(( 1 + 2 ))")]]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(words("echo a#b '#' # rest"), [vec![bare("echo")], vec![bare("a#b")], vec![single("#")]]);
+    }
+
+    #[test]
+    fn operators_without_spaces() {
+        let list = parse("a|b&&c||d;e&").unwrap();
+        let connectors: Vec<_> = list.items.iter().map(|(connector, _)| *connector).collect();
+        assert_eq!(connectors, [Connector::Seq, Connector::And, Connector::Or, Connector::Seq]);
+        assert_eq!(list.items[0].1.commands.len(), 2);
+        assert!(!list.items[2].1.background);
+        assert!(list.items[3].1.background);
+    }
+
+    #[test]
+    fn redirections() {
+        let list = parse("ls>out 2>&1 <in 3>|f >>log 4<>rw").unwrap();
+        let command = &list.items[0].1.commands[0];
+        let redirects: Vec<_> = command.redirects.iter().map(|r| (r.fd, r.op, r.target.unquoted())).collect();
+        assert_eq!(redirects, [
+            (1, RedirectOp::Write, "out".to_string()),
+            (2, RedirectOp::DupOut, "1".to_string()),
+            (0, RedirectOp::Read, "in".to_string()),
+            (3, RedirectOp::Clobber, "f".to_string()),
+            (1, RedirectOp::Append, "log".to_string()),
+            (4, RedirectOp::ReadWrite, "rw".to_string()),
+        ]);
+        assert_eq!(command.words.len(), 1);
+    }
+
+    #[test]
+    fn heredocs() {
+        let list = parse("cat <<EOF\nhi $x\nEOF").unwrap();
+        let redirect = &list.items[0].1.commands[0].redirects[0];
+        assert_eq!((redirect.op, &redirect.target.parts), (RedirectOp::HereDoc, &vec![double("hi $x\n")]));
+
+        let list = parse("cat <<-'E'\n\ttab\n\tE\necho after").unwrap();
+        let redirect = &list.items[0].1.commands[0].redirects[0];
+        assert_eq!(redirect.target.parts, [single("tab\n")]);
+        assert_eq!(list.items.len(), 2);
+
+        assert_eq!(
+            parse("cat <<EOF").unwrap_err(),
+            "Syntax error: here-document wanted 'EOF' before the end of input"
+        );
+    }
+
+    #[test]
+    fn subshells_and_groups() {
+        let list = parse("(a; b) | { c; }").unwrap();
+        let commands = &list.items[0].1.commands;
+        assert_eq!(commands[0].subshell.as_deref(), Some("a; b"));
+        assert_eq!(commands[1].group.as_ref().map(|group| group.list.items.len()), Some(1));
+    }
+
+    #[test]
+    fn error_columns() {
+        assert_eq!(parse("echo \"unterminated").unwrap_err(), "Syntax error: unterminated quote at column 6");
+        assert_eq!(parse("a | | b").unwrap_err(), "Syntax error near '|' at column 5");
+        assert_eq!(parse("echo a;;").unwrap_err(), "Syntax error near ';' at column 8");
+        assert_eq!(parse("x;\n  &&").unwrap_err(), "Syntax error near '&&' at line 2, column 3");
+    }
+
+    #[test]
+    fn incomplete_lines() {
+        assert!(incomplete("echo 'a"));
+        assert!(incomplete("a |"));
+        assert!(incomplete("a &&"));
+        assert!(incomplete("echo a\\"));
+        assert!(incomplete("cat <<EOF\nbody"));
+        assert!(incomplete("{ a;"));
+        assert!(!incomplete("echo a # b \\"));
+        assert!(!incomplete("echo done"));
+    }
+
+    #[test]
+    fn dump_shows_the_tree() {
+        let list = parse("a 'b' >out | c && d &").unwrap();
+        assert_eq!(dump(&list), "\
+pipeline
+  command
+    word Bare(\"a\")
+    word Single(\"b\")
+    redirect 1> Bare(\"out\")
+  command
+    word Bare(\"c\")
+&& pipeline &
+  command
+    word Bare(\"d\")
+");
+    }
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{output, Sandbox};
+use std::fs;
+
+/// Runs `script` as a script file, so each line is parsed after the ones
+/// before it have run.
+fn script(sandbox: &Sandbox, script: &str) -> String {
+    let path = sandbox.write("script", script);
+    output(sandbox.command(&[path.to_str().unwrap()])).stdout
+}
+
+#[test]
+fn hash_inside_quoted_alias_value() {
+    let sandbox = Sandbox::new();
+    let out = script(&sandbox, "alias note='echo # todo' # comment\nalias tag='echo a#b'\nalias note\ntag\n");
+    assert_eq!(out, "alias note='echo # todo'\na#b\n");
+    let saved = fs::read_to_string(sandbox.path(".config/falsh/aliases")).unwrap();
+    assert_eq!(saved, "alias note='echo # todo'\nalias tag='echo a#b'\n");
+}
+
+#[test]
+fn alias_file_keeps_quoted_hash() {
+    let sandbox = Sandbox::new();
+    sandbox.write(".config/falsh/aliases", "# a comment\nalias note='echo a#b \"# c\" # d'\n");
+    let run = sandbox.session(&["--norc"], "alias\nnote\n", &[]);
+    assert!(run.stdout.contains("alias note='echo a#b \"# c\" # d'\n"), "{}", run.stdout);
+    assert!(run.stdout.contains("\na#b # c\n"), "{}", run.stdout);
+}
+
+#[test]
+fn subshells_inherit_aliases() {
+    let sandbox = Sandbox::new();
+    assert_eq!(script(&sandbox, "alias hi='echo hi'\n(hi)\n(hi) | cat\n"), "hi\nhi\n");
+}
+
+#[test]
+fn unalias_all_clears_and_saves() {
+    let sandbox = Sandbox::new();
+    let out = script(&sandbox, "alias a='echo a' b='echo b'\nunalias -a\nalias\nunalias a; echo $?\n");
+    assert_eq!(out, "unalias: a: not found\n1\n");
+    assert_eq!(fs::read_to_string(sandbox.path(".config/falsh/aliases")).unwrap(), "");
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::{falsh, output, Sandbox};
+
+#[test]
+fn command_and_builtin_skip_aliases() {
+    assert_eq!(falsh("alias ls='echo aliased'\ncommand ls -d /"), "/\n");
+    assert_eq!(falsh("alias cd='echo nope'\nbuiltin cd /; builtin pwd"), "/\n");
+    assert_eq!(falsh("builtin ls; echo $?"), "builtin: ls: not a shell builtin\n1\n");
+}
+
+#[test]
+fn commands_without_words_still_run() {
+    assert_eq!(falsh("x=5; echo $x"), "5\n");
+    assert_eq!(falsh("> out; echo ok"), "ok\n");
+    assert_eq!(falsh("e=; $e; echo ok"), "ok\n");
+}
+
+#[test]
+fn running_a_directory_or_unexecutable_file() {
+    let sandbox = Sandbox::new();
+    sandbox.mkdir("somedir");
+    sandbox.write("notexec", "echo hi\n");
+    let run = sandbox.run("./somedir");
+    assert_eq!((run.stdout.as_str(), run.status), ("falsh: ./somedir: is a directory\n", 126));
+    let run = sandbox.run("./notexec");
+    assert_eq!((run.stdout.as_str(), run.status), ("falsh: ./notexec: permission denied\n", 126));
+    let run = sandbox.run("no-such-command-here");
+    assert_eq!((run.stdout.as_str(), run.status), ("falsh: no-such-command-here: command not found\n", 127));
+}
+
+#[test]
+fn correction_suggests_a_close_command() {
+    let sandbox = Sandbox::new();
+    let tool = sandbox.write("bin/mytool", "#!/bin/sh\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = sandbox.path("bin");
+    let vars = [("FALSH_CORRECT", "1"), ("PATH", path.to_str().unwrap())];
+    let run = sandbox.run_with("mytoal; echo $?", &vars);
+    assert_eq!(run.stdout, "falsh: command not found: mytoal. Did you mean 'mytool'?\n127\n");
+    let run = sandbox.run_with("ecoh hi", &vars);
+    assert_eq!(run.stdout, "falsh: command not found: ecoh. Did you mean 'echo'?\n");
+    // Without the variable, no guessing.
+    let run = sandbox.run_with("mytoal", &[("PATH", path.to_str().unwrap())]);
+    assert_eq!(run.stdout, "falsh: mytoal: command not found\n");
+}
+
+#[test]
+fn path_with_slash_runs_the_file_not_the_builtin() {
+    let sandbox = Sandbox::new();
+    let script = sandbox.write("cd", "#!/bin/sh\necho local cd script\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    assert_eq!(sandbox.run("./cd /; pwd").stdout, format!("local cd script\n{}\n", sandbox.dir.display()));
+    assert_eq!(sandbox.run("alias ./cd=true; type ./cd").stdout, "alias: './cd=true': invalid alias name\n./cd is ./cd\n");
+}
+
+#[test]
+fn test_operator_table() {
+    let sandbox = Sandbox::new();
+    sandbox.write("file", "data");
+    sandbox.write("empty", "");
+    sandbox.mkdir("dir");
+    let cases = [
+        ("-e file", 0), ("-e missing", 1), ("-f file", 0), ("-f dir", 1),
+        ("-d dir", 0), ("-d file", 1), ("-s file", 0), ("-s empty", 1),
+        ("-r file", 0), ("-x dir", 0), ("-L file", 1),
+        ("-n abc", 0), ("-n ''", 1), ("-z ''", 0), ("-z abc", 1),
+        ("abc = abc", 0), ("abc == abd", 1), ("abc != abd", 0), ("b '>' a", 0), ("b '<' a", 1),
+        ("10 -eq 10", 0), ("10 -ne 10", 1), ("9 -lt 10", 0), ("10 -le 9", 1),
+        ("10 -gt 9", 0), ("9 -ge 10", 1), ("-5 -lt 2", 0),
+        ("file -ef ./file", 0), ("file -ef empty", 1), ("file -nt missing", 0), ("missing -ot file", 0),
+        ("! -f file", 1), ("-f file -a -d dir", 0), ("-f missing -o -d dir", 0),
+        ("'(' -f missing -o -d dir ')' -a 1 -eq 1", 0), ("-f = -f", 0),
+        ("", 1), ("abc", 0), ("''", 1), ("!", 0),
+        ("1 -eq x", 2), ("-q x", 2), ("a b", 2), ("'(' a", 2),
+    ];
+    for (expr, expected) in cases {
+        let script = format!("test {expr}; echo $?; [ {expr} ]; echo $?");
+        let out = sandbox.run(&script).stdout;
+        let statuses: Vec<&str> = out.lines().filter(|line| line.parse::<i32>().is_ok()).collect();
+        assert_eq!(statuses, [expected.to_string(), expected.to_string()], "test {}: {}", expr, out);
+    }
+}
+
+#[test]
+fn bracket_needs_its_closing_bracket() {
+    assert_eq!(falsh("[ -n x; echo $?"), "[: missing ']'\n2\n");
+    assert_eq!(falsh("[ 1 -eq x ]"), "[: x: integer expression expected\n");
+}
+
+#[test]
+fn exit_trap_runs_after_a_command_string() {
+    let run = Sandbox::new().run("trap 'echo bye $?' EXIT; echo hi; false");
+    assert_eq!((run.stdout.as_str(), run.status), ("hi\nbye 1\n", 1));
+}
+
+#[test]
+fn traps_are_listed_and_reset() {
+    let out = falsh("trap 'echo it'\\''s' INT TERM; trap true EXIT; trap");
+    assert_eq!(out, "trap -- 'true' EXIT\ntrap -- 'echo it'\"'\"'s' INT\ntrap -- 'echo it'\"'\"'s' TERM\n");
+    assert_eq!(falsh("trap 'echo got' USR1 INT; trap - SIGUSR1; trap"), "trap -- 'echo got' INT\n");
+    // With the trap gone the signal kills the shell again.
+    let run = Sandbox::new().run("trap 'echo got' USR1; trap - USR1; sh -c 'kill -USR1 $PPID'; echo after");
+    assert_eq!(run.stdout, "");
+    assert_ne!(run.status, 0);
+    assert_eq!(falsh("trap 'echo got' USR1; sh -c 'kill -USR1 $PPID'; echo after"), "after\ngot\n");
+    assert_eq!(falsh("trap x BOGUS; echo $?"), "trap: BOGUS: invalid signal specification\n1\n");
+}
+
+#[test]
+fn trap_lists_signals() {
+    let out = falsh("trap -l");
+    assert_eq!(out.lines().count(), 1);
+    for name in ["SIGHUP", "SIGINT", "SIGTERM", "SIGUSR1"] {
+        assert!(out.split_whitespace().any(|word| word == name), "{}", out);
+    }
+}
+
+#[test]
+fn echo_flags() {
+    assert_eq!(falsh("echo -n a; echo -e 'b\\tc'; echo -E 'd\\te'; echo -ne 'x\\n'; echo -nx; echo -- -n"), "ab\tc\nd\\te\nx\n-nx\n-- -n\n");
+}
+
+#[test]
+fn printf_formats() {
+    assert_eq!(falsh("printf '[%5s][%-4d][%.2f][%x][%.3s]\\n' ab 7 3.14159 255 abcdef"), "[   ab][7   ][3.14][ff][abc]\n");
+    // The format is reused until the arguments run out.
+    assert_eq!(falsh("printf '%s=%d\\n' a 1 b 2 c"), "a=1\nb=2\nc=0\n");
+}
+
+#[test]
+fn read_prompt_and_timeout() {
+    // The prompt is only shown when reading from a terminal.
+    let run = Sandbox::new().run("read -p 'name? ' N <<< bob; echo \"[$N]\"");
+    assert_eq!((run.stdout.as_str(), run.stderr.as_str()), ("[bob]\n", ""));
+    // Timing out is not end of input, which gives 1.
+    let out = falsh("sleep 1 | read -t 0.2 Z; echo $? \"[$Z]\"; read -t 1 E < /dev/null; echo $?");
+    let codes: Vec<&str> = out.split_whitespace().collect();
+    assert!(codes[0].parse::<i32>().unwrap() > 128, "{}", out);
+    assert_eq!(&codes[1..], ["[]", "1"]);
+}
+
+#[test]
+fn type_describes_each_kind_of_command() {
+    let sandbox = Sandbox::new();
+    let tool = sandbox.write("bin/mytool", "#!/bin/sh\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let script = sandbox.write("script", "alias ll='ls -l'\ntype ll cd mytool nosuch; echo $?\ntype -t cd\n");
+    let mut cmd = sandbox.command(&[script.to_str().unwrap()]);
+    cmd.env("PATH", sandbox.path("bin"));
+    let expected = format!(
+        "ll is aliased to 'ls -l'\ncd is a shell builtin\nmytool is {}\ntype: nosuch: not found\n1\nbuiltin\n",
+        tool.display()
+    );
+    assert_eq!(output(cmd).stdout, expected);
+}
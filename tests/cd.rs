@@ -0,0 +1,47 @@
+mod common;
+
+use common::Sandbox;
+
+/// A sandbox holding a `projects` directory and a one-letter `h`.
+fn dirs() -> Sandbox {
+    let sandbox = Sandbox::new();
+    sandbox.mkdir("projects");
+    sandbox.mkdir("h");
+    sandbox
+}
+
+#[test]
+fn typo_is_only_suggested_by_default() {
+    let sandbox = dirs();
+    let run = sandbox.run("cd projetcs; echo s=$?; pwd");
+    let expected = format!(
+        "cd: did you mean 'projects'?\ns=1\n{}\n",
+        sandbox.dir.display()
+    );
+    assert_eq!(run.stdout, expected);
+}
+
+#[test]
+fn typo_is_corrected_with_cdspell() {
+    let sandbox = dirs();
+    let run = sandbox.run_with("cd projetcs; pwd", &[("FALSH_CDSPELL", "1")]);
+    let projects = sandbox.path("projects");
+    assert_eq!(run.stdout, format!("{}\n{}\n", "projects", projects.display()));
+}
+
+#[test]
+fn short_targets_are_never_corrected() {
+    let sandbox = dirs();
+    let run = sandbox.run_with("cd x; pwd", &[("FALSH_CDSPELL", "1")]);
+    assert_eq!(run.stdout, format!("cd failed: No such file or directory (os error 2)\n{}\n", sandbox.dir.display()));
+}
+
+#[test]
+fn dash_goes_back_to_oldpwd() {
+    let sandbox = dirs();
+    let run = sandbox.run_with("cd /; cd -; pwd; cd -", &[("FALSH_CDSPELL", "1")]);
+    let dir = sandbox.dir.display();
+    assert_eq!(run.stdout, format!("{}\n{}\n/\n", dir, dir));
+    let run = sandbox.run_with("cd -", &[("OLDPWD", "")]);
+    assert!(!run.stdout.contains("Did you mean"), "{}", run.stdout);
+}
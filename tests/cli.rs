@@ -0,0 +1,82 @@
+mod common;
+
+use common::{output, Sandbox};
+
+#[test]
+fn parse_dump() {
+    let sandbox = Sandbox::new();
+    let run = output(sandbox.command(&["--parse-dump", "ls -l | wc"]));
+    assert_eq!(run.stdout, "pipeline\n  command\n    word Bare(\"ls\")\n    word Bare(\"-l\")\n  command\n    word Bare(\"wc\")\n");
+    assert_eq!(run.status, 0);
+
+    let run = output(sandbox.command(&["--parse-dump", "ls |"]));
+    assert_eq!(run.status, 2);
+    assert!(run.stdout.contains("Syntax error"), "{}", run.stdout);
+}
+
+#[test]
+fn config_files_follow_xdg_config_home() {
+    let sandbox = Sandbox::new();
+    let config = sandbox.mkdir("xdg");
+    sandbox.write("xdg/falsh/falshrc", "echo from-xdg-rc\n");
+    sandbox.write(".falshrc", "echo from-legacy-rc\n");
+    sandbox.mkdir("bin");
+    let xdg = [("XDG_CONFIG_HOME", config.to_str().unwrap())];
+
+    let run = sandbox.session(&[], "addToPath bin\n", &xdg);
+    assert!(run.stdout.contains("from-xdg-rc"), "{}", run.stdout);
+    assert!(!run.stdout.contains("from-legacy-rc"), "{}", run.stdout);
+
+    let saved = std::fs::read_to_string(config.join("falsh/path")).unwrap();
+    assert_eq!(saved, "~/bin\n");
+    assert!(!sandbox.path(".falsh_path").exists());
+    assert!(!sandbox.path(".config/falsh/path").exists());
+}
+
+#[test]
+fn legacy_rc_file_is_used_when_xdg_has_none() {
+    let sandbox = Sandbox::new();
+    sandbox.write(".falshrc", "echo from-legacy-rc\n");
+    let run = sandbox.session(&[], "", &[]);
+    assert!(run.stdout.contains("from-legacy-rc"), "{}", run.stdout);
+}
+
+#[test]
+fn version_is_one_line() {
+    let sandbox = Sandbox::new();
+    let run = output(sandbox.command(&["--version"]));
+    let line = run.stdout.strip_suffix('\n').unwrap();
+    assert!(!line.contains('\n'), "{}", run.stdout);
+    assert!(line.starts_with(&format!("falsh {} ", env!("CARGO_PKG_VERSION"))), "{}", line);
+    assert!(line.split(' ').any(|field| field.starts_with("features=")), "{}", line);
+    assert_eq!(sandbox.run("version").stdout, run.stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn event_fd_gets_json_lines() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let sandbox = Sandbox::new();
+    let events = std::fs::File::create(sandbox.path("events")).unwrap();
+    let fd = events.as_raw_fd();
+    let mut cmd = sandbox.command(&["--norc", "-c", "echo \"a\\b\"\nfalse"]);
+    cmd.env("FALSH_EVENT_FD", "9");
+    // Descriptor 9 in the child is the events file, left open across exec.
+    unsafe {
+        cmd.pre_exec(move || {
+            let result = if fd == 9 { libc::fcntl(9, libc::F_SETFD, 0) } else { libc::dup2(fd, 9) };
+            if result < 0 { Err(std::io::Error::last_os_error()) } else { Ok(()) }
+        });
+    }
+    let run = output(cmd);
+    assert_eq!(run.stdout, "a\\b\n");
+    assert_eq!(
+        std::fs::read_to_string(sandbox.path("events")).unwrap(),
+        concat!(
+            "{\"type\":\"start\",\"cmd\":\"echo \\\"a\\\\b\\\"\\nfalse\"}\n",
+            "{\"type\":\"exec\",\"cmd\":\"echo \\\"a\\\\b\\\"\\nfalse\",\"status\":1}\n",
+        )
+    );
+}
@@ -0,0 +1,122 @@
+//! Runs the falsh binary for the integration tests, each in a scratch
+//! directory that also serves as `HOME`, so nothing touches the real
+//! config files.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A scratch directory, removed again when the test is done.
+pub struct Sandbox {
+    pub dir: PathBuf,
+}
+
+/// What a falsh run printed and the status it exited with.
+pub struct Run {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32,
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!(
+            "falsh-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        Sandbox { dir: dir.canonicalize().unwrap() }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Creates `name` (and its parents) holding `contents`.
+    pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    pub fn mkdir(&self, name: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// A falsh command started in the sandbox with `HOME` pointing at it.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_falsh"));
+        cmd.args(args)
+            .current_dir(&self.dir)
+            .env("HOME", &self.dir)
+            .env_remove("XDG_CONFIG_HOME")
+            .stdin(Stdio::null());
+        for (key, _) in env::vars_os() {
+            if key.to_string_lossy().starts_with("FALSH_") {
+                cmd.env_remove(key);
+            }
+        }
+        cmd
+    }
+
+    /// Runs `falsh --norc -c script`.
+    pub fn run(&self, script: &str) -> Run {
+        self.run_with(script, &[])
+    }
+
+    /// Runs `falsh --norc -c script` with extra environment variables.
+    pub fn run_with(&self, script: &str, vars: &[(&str, &str)]) -> Run {
+        let mut cmd = self.command(&["--norc", "-c", script]);
+        cmd.envs(vars.iter().copied());
+        output(cmd)
+    }
+}
+
+impl Sandbox {
+    /// Runs an interactive falsh (with `args`, such as `--norc`) that reads
+    /// `input` from a pipe, the way the rc file and REPL paths run.
+    pub fn session(&self, args: &[&str], input: &str, vars: &[(&str, &str)]) -> Run {
+        let mut cmd = self.command(args);
+        cmd.envs(vars.iter().copied()).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = cmd.spawn().unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        Run {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status.code().unwrap_or(-1),
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+pub fn output(mut cmd: Command) -> Run {
+    let output = cmd.output().unwrap();
+    Run {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        status: output.status.code().unwrap_or(-1),
+    }
+}
+
+/// Runs `script` in a fresh sandbox and returns its standard output.
+pub fn falsh(script: &str) -> String {
+    Sandbox::new().run(script).stdout
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::falsh;
+
+#[test]
+fn arithmetic_expansion() {
+    assert_eq!(falsh("echo $(( 2 + 3 * 4 ))"), "14\n");
+    assert_eq!(falsh("i=4; echo $(( i + 1 )) $(( $i * 2 ))"), "5 8\n");
+    assert!(falsh("echo $(( 1 / 0 ))").contains("division by zero"));
+}
+
+#[test]
+fn arithmetic_assignment() {
+    assert_eq!(falsh("i=0; echo $(( i++ )) $(( i++ )) $(( i += 10 )); echo $i"), "0 1 12\n12\n");
+}
+
+#[test]
+fn brace_expansion() {
+    assert_eq!(falsh("echo {1..5} x{a,b} \"{a,b}\""), "1 2 3 4 5 xa xb {a,b}\n");
+    assert_eq!(falsh("mkdir -p src/{lib,bin}; ls src"), "bin\nlib\n");
+}
+
+#[test]
+fn invalid_utf8_in_command_substitution() {
+    let out = falsh("x=$(sh -c 'printf \"\\377\\376ok\"'); echo \"$x\"; echo after");
+    assert_eq!(out, "\u{FFFD}\u{FFFD}ok\nafter\n");
+}
+
+#[test]
+fn here_documents() {
+    let out = falsh("X=val\ncat <<-EOF\n\tindented $X\n\t\ttwo tabs\n\tEOF\ncat <<'END'\n\tkept $X\nEND\necho after");
+    assert_eq!(out, "indented val\ntwo tabs\n\tkept $X\nafter\n");
+}
+
+#[test]
+fn here_strings() {
+    assert_eq!(falsh("X='a b'; tr a-z A-Z <<< \"$X  c\"; cat <<<plain; wc -l <<< ''"), "A B  C\nplain\n1\n");
+}
+
+#[test]
+fn process_substitution() {
+    let out = falsh("diff <(printf 'a\\nb\\n') <(printf 'a\\nc\\n'); echo $?; cat <(echo sub)");
+    assert_eq!(out, "2c2\n< b\n---\n> c\n1\nsub\n");
+    assert_eq!(falsh("echo hi > >(tr a-z A-Z); echo next"), "HI\nnext\n");
+}
+
+#[test]
+fn default_values() {
+    let out = falsh("E=; echo \"[${U:-def}] [${E:-def}] [${E-def}] [${U-def}]\"; echo \"[${U:=set}] $U\" ${E:+alt} ${U:+alt}");
+    assert_eq!(out, "[def] [def] [] [def]\n[set] set alt\n");
+}
+
+#[test]
+fn trimming_and_length() {
+    let out = falsh("P=dir/sub/file.tar.gz; echo ${P#*/} ${P##*/} ${P%.*} ${P%%.*} ${#P} ${P#nomatch}");
+    assert_eq!(out, "sub/file.tar.gz file.tar.gz dir/sub/file.tar dir/sub/file 19 dir/sub/file.tar.gz\n");
+}
+
+#[test]
+fn ansi_c_quoting() {
+    assert_eq!(falsh("echo $'a\\tb\\nc' $'it\\'s' $'\\x41\\101' '$a\\tb'"), "a\tb\nc it's AA $a\\tb\n");
+}
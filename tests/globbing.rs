@@ -0,0 +1,57 @@
+mod common;
+
+use common::Sandbox;
+
+/// A sandbox holding `file1.txt`, `file2.txt` and `a.log`.
+fn files() -> Sandbox {
+    let sandbox = Sandbox::new();
+    for name in ["file1.txt", "file2.txt", "a.log"] {
+        sandbox.write(name, "");
+    }
+    sandbox
+}
+
+#[test]
+fn patterns_expand_when_they_match() {
+    let sandbox = files();
+    assert_eq!(sandbox.run("echo *.txt file[1-3].txt ?.log").stdout, "file1.txt file2.txt file1.txt file2.txt a.log\n");
+}
+
+#[test]
+fn unmatched_patterns_stay_literal_by_default() {
+    let sandbox = files();
+    assert_eq!(sandbox.run("echo x*.z ?.q file[4-9].txt").stdout, "x*.z ?.q file[4-9].txt\n");
+}
+
+#[test]
+fn nullglob_drops_unmatched_patterns() {
+    let sandbox = files();
+    let run = sandbox.run("set -o nullglob; echo A x*.z ?.q file[4-9].txt B *.log");
+    assert_eq!(run.stdout, "A B a.log\n");
+}
+
+#[test]
+fn default_nullglob_and_failglob_compared() {
+    let sandbox = files();
+    let script = "echo A x*.z B *.log; echo s=$?";
+    assert_eq!(sandbox.run(script).stdout, "A x*.z B a.log\ns=0\n");
+    assert_eq!(sandbox.run(&format!("set -o nullglob; {}", script)).stdout, "A B a.log\ns=0\n");
+    let run = sandbox.run(&format!("set -o failglob; {}", script));
+    assert_eq!(run.stdout, "falsh: no matches for: x*.z\ns=1\n");
+    // Matching patterns are unaffected.
+    assert_eq!(sandbox.run("set -o failglob; echo *.log").stdout, "a.log\n");
+}
+
+#[test]
+fn noglob_passes_star_to_the_command() {
+    let sandbox = files();
+    let run = sandbox.run("set -o noglob; sh -c 'for a; do echo \"<$a>\"; done' sh * file?.txt");
+    assert_eq!(run.stdout, "<*>\n<file?.txt>\n");
+}
+
+#[test]
+fn set_f_toggles_globbing() {
+    let sandbox = files();
+    let run = sandbox.run("set -f; echo *.log; set +f; echo *.log; set -o noglob; echo *.log; set +o noglob; echo *.log");
+    assert_eq!(run.stdout, "*.log\na.log\n*.log\na.log\n");
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::Sandbox;
+use std::fs;
+
+/// Gives the sandbox a `bin` directory with one executable in it.
+fn bin_dir(sandbox: &Sandbox, dir: &str) {
+    let tool = sandbox.write(&format!("{}/mytool", dir), "#!/bin/sh\necho tool ran\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(tool, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+#[test]
+fn tilde_entry_is_expanded_live_and_saved_as_typed() {
+    let sandbox = Sandbox::new();
+    bin_dir(&sandbox, "bin");
+    let run = sandbox.run("addToPath ~/bin; mytool; echo $PATH");
+    assert!(!run.stdout.contains("does not exist"), "{}", run.stdout);
+    let lines: Vec<&str> = run.stdout.lines().collect();
+    assert_eq!(lines[0], "tool ran");
+    assert!(lines[1].split(':').any(|dir| dir == sandbox.path("bin").to_str().unwrap()), "{}", lines[1]);
+    assert!(!lines[1].contains('~'));
+    let saved = fs::read_to_string(sandbox.path(".config/falsh/path")).unwrap();
+    assert_eq!(saved, "~/bin\n");
+}
+
+#[test]
+fn variable_entry_is_saved_as_typed() {
+    let sandbox = Sandbox::new();
+    bin_dir(&sandbox, "bin");
+    let run = sandbox.run("addToPath '$HOME/bin'; mytool");
+    assert_eq!(run.stdout, "tool ran\n");
+    let saved = fs::read_to_string(sandbox.path(".config/falsh/path")).unwrap();
+    assert_eq!(saved, "$HOME/bin\n");
+}
+
+#[test]
+fn missing_directory_warns() {
+    let sandbox = Sandbox::new();
+    let run = sandbox.run("addToPath nothing");
+    assert!(run.stderr.contains("Warning: path nothing does not exist."), "{}", run.stderr);
+    assert_eq!(run.stdout, "");
+}
+
+#[test]
+fn saved_empty_directory_keeps_subshell_output_clean() {
+    let sandbox = Sandbox::new();
+    sandbox.mkdir("empty");
+    let run = sandbox.run("addToPath ~/empty");
+    assert!(run.stderr.contains("contains no executable files"), "{}", run.stderr);
+    assert_eq!(run.stdout, "");
+    let run = sandbox.run("X=$( (echo hi) ); echo \"[$X]\"");
+    assert_eq!(run.stdout, "[hi]\n");
+    assert_eq!(run.stderr, "");
+}
+
+#[test]
+fn relative_entry_becomes_absolute() {
+    let sandbox = Sandbox::new();
+    bin_dir(&sandbox, "scripts");
+    let home = sandbox.mkdir("home");
+    let run = sandbox.run_with("addToPath ./scripts; cd /; mytool; echo $PATH", &[("HOME", home.to_str().unwrap())]);
+    let lines: Vec<&str> = run.stdout.lines().collect();
+    assert_eq!(lines[0], "tool ran");
+    let scripts = sandbox.path("scripts");
+    assert_eq!(lines[1].split(':').next_back(), scripts.to_str());
+    let saved = fs::read_to_string(home.join(".config/falsh/path")).unwrap();
+    assert_eq!(saved.trim_end(), scripts.to_str().unwrap());
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{falsh, Sandbox};
+
+#[test]
+fn status_of_last_stage() {
+    assert_eq!(falsh("false | true; echo $?"), "0\n");
+    assert_eq!(falsh("true | false; echo $?"), "1\n");
+    assert_eq!(Sandbox::new().run("true | false").status, 1);
+}
+
+#[test]
+fn pipefail_takes_rightmost_failure() {
+    assert_eq!(falsh("set -o pipefail; false | true; echo $?"), "1\n");
+    assert_eq!(falsh("set -o pipefail; sh -c 'exit 3' | false | true; echo $?"), "1\n");
+    assert_eq!(falsh("set -o pipefail; sh -c 'exit 3' | true; echo $?"), "3\n");
+    assert_eq!(falsh("set -o pipefail; true | true; echo $?"), "0\n");
+    assert_eq!(falsh("set -o pipefail; set +o pipefail; false | true; echo $?"), "0\n");
+}
+
+#[test]
+fn early_exit_of_reader_is_quiet() {
+    let run = Sandbox::new().run("yes | head -1");
+    assert_eq!(run.stdout, "y\n");
+    assert_eq!(run.stderr, "");
+    assert_eq!(run.status, 0);
+
+    let run = Sandbox::new().run("echo {1..20000} | head -c 2; echo; seq 1 100000 | head -1");
+    assert_eq!(run.stdout, "1 \n1\n");
+    assert_eq!(run.stderr, "");
+}
+
+#[test]
+fn subshells_inherit_options() {
+    assert_eq!(falsh("set -o pipefail; (false | true); echo $?"), "1\n");
+    assert_eq!(falsh("set +o globstar; (set -o) | grep globstar"), "globstar    off\n");
+}
+
+#[test]
+fn background_commands_do_not_block() {
+    let started = std::time::Instant::now();
+    let out = falsh("sleep 3 > /dev/null 2>&1 & echo started; echo $! | grep -c '^[0-9][0-9]*$'");
+    assert_eq!(out, "started\n1\n");
+    assert!(started.elapsed().as_secs() < 2, "{:?}", started.elapsed());
+    assert_eq!(falsh("sh -c 'sleep 0.3; echo late' & echo early"), "early\nlate\n");
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::Sandbox;
+use std::fs;
+
+#[test]
+fn quoted_target_with_spaces() {
+    let sandbox = Sandbox::new();
+    let run = sandbox.run("echo hi > \"my file.txt\"; cat 'my file.txt'");
+    assert_eq!(run.stdout, "hi\n");
+    assert_eq!(fs::read_to_string(sandbox.path("my file.txt")).unwrap(), "hi\n");
+}
+
+#[test]
+fn unquoted_target_with_spaces_warns() {
+    let sandbox = Sandbox::new();
+    let run = sandbox.run("echo hi > my file.txt");
+    assert!(run.stderr.contains("Warning: 'file.txt' after the redirection target"), "{}", run.stderr);
+    assert_eq!(run.stdout, "");
+    assert_eq!(fs::read_to_string(sandbox.path("my")).unwrap(), "hi file.txt\n");
+    assert!(!sandbox.path("my file.txt").exists());
+}
+
+#[test]
+fn explicit_stdin_and_read_write() {
+    let sandbox = Sandbox::new();
+    sandbox.write("in", "from file\n");
+    assert_eq!(sandbox.run("cat 0< in").stdout, "from file\n");
+    assert_eq!(sandbox.run("cat 0<in; cat < in").stdout, "from file\nfrom file\n");
+    assert_eq!(sandbox.run("cat <> in").stdout, "from file\n");
+    // `<>` creates the file without truncating it.
+    sandbox.run("cat <> created");
+    assert_eq!(fs::read_to_string(sandbox.path("created")).unwrap(), "");
+    assert_eq!(sandbox.run("cat 3<in <&3").stdout, "from file\n");
+}
+
+#[test]
+fn input_redirection_in_pipelines() {
+    let sandbox = Sandbox::new();
+    sandbox.write("in.txt", "b\na\nc\na\n");
+    assert_eq!(sandbox.run("sort < in.txt | head -2").stdout, "a\na\n");
+    // An explicit `<` on a later stage wins over the pipe, as in bash.
+    assert_eq!(sandbox.run("echo ignored | sort < in.txt | uniq").stdout, "a\nb\nc\n");
+}
+
+#[test]
+fn append_keeps_what_was_there() {
+    let sandbox = Sandbox::new();
+    sandbox.run("echo one > log; echo two >> log; echo three >>log; echo new >> fresh");
+    assert_eq!(fs::read_to_string(sandbox.path("log")).unwrap(), "one\ntwo\nthree\n");
+    assert_eq!(fs::read_to_string(sandbox.path("fresh")).unwrap(), "new\n");
+}
+
+#[test]
+fn stderr_to_a_file() {
+    let sandbox = Sandbox::new();
+    let run = sandbox.run("sh -c 'echo oops >&2; echo out' 2> err; sh -c 'echo again >&2' 2>> err; echo ok 2> empty");
+    assert_eq!((run.stdout.as_str(), run.stderr.as_str()), ("out\nok\n", ""));
+    assert_eq!(fs::read_to_string(sandbox.path("err")).unwrap(), "oops\nagain\n");
+    assert_eq!(fs::read_to_string(sandbox.path("empty")).unwrap(), "");
+}
+
+#[test]
+fn duplicating_descriptors() {
+    let sandbox = Sandbox::new();
+    let run = sandbox.run("sh -c 'echo out; echo err >&2' > both 2>&1; sh -c 'echo err >&2' 2>&1 > only-out | tr e E");
+    assert_eq!((run.stdout.as_str(), run.stderr.as_str()), ("Err\n", ""));
+    assert_eq!(fs::read_to_string(sandbox.path("both")).unwrap(), "out\nerr\n");
+    assert_eq!(fs::read_to_string(sandbox.path("only-out")).unwrap(), "");
+    assert_eq!(sandbox.run("echo to-err 1>&2").stderr, "to-err\n");
+}
+
+#[test]
+fn pipe_with_stderr() {
+    let run = Sandbox::new().run("sh -c 'echo out; echo err >&2' |& sort; sh -c 'echo err >&2' | wc -l");
+    assert_eq!(run.stdout, "err\nout\n0\n");
+    assert_eq!(run.stderr, "err\n");
+}
+
+#[test]
+fn noclobber_and_override() {
+    let sandbox = Sandbox::new();
+    sandbox.write("kept", "old\n");
+    let run = sandbox.run("set -o noclobber; echo new > kept; echo $?; echo more >> kept; echo fresh > fresh");
+    assert_eq!(run.stdout, "kept: cannot overwrite existing file\n1\n");
+    assert_eq!(fs::read_to_string(sandbox.path("kept")).unwrap(), "old\nmore\n");
+    assert_eq!(fs::read_to_string(sandbox.path("fresh")).unwrap(), "fresh\n");
+    sandbox.run("set -C; echo replaced >| kept");
+    assert_eq!(fs::read_to_string(sandbox.path("kept")).unwrap(), "replaced\n");
+}
+
+#[test]
+fn numbered_descriptors() {
+    let sandbox = Sandbox::new();
+    sandbox.write("in", "line\n");
+    let run = sandbox.run("sh -c 'echo three >&3; cat <&4' 3> three 4< in; echo gone 4>&-");
+    assert_eq!(run.stdout, "line\ngone\n");
+    assert_eq!(fs::read_to_string(sandbox.path("three")).unwrap(), "three\n");
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::{output, Sandbox};
+
+#[test]
+fn trailing_comments_in_rc_file() {
+    let sandbox = Sandbox::new();
+    let rc = sandbox.write("rc", "# whole line\necho a#b \"c # d\" 'e#f' # trailing\n  # indented\n");
+    let run = sandbox.session(&["--rcfile", rc.to_str().unwrap()], "", &[]);
+    assert!(run.stdout.contains("a#b c # d e#f\n"), "{}", run.stdout);
+    assert!(!run.stdout.contains("trailing"), "{}", run.stdout);
+}
+
+#[test]
+fn trailing_comments_in_scripts() {
+    let sandbox = Sandbox::new();
+    let script = sandbox.write("script", "echo one # not printed\necho \"two#\" two#three\n");
+    let run = output(sandbox.command(&[script.to_str().unwrap()]));
+    assert_eq!(run.stdout, "one\ntwo# two#three\n");
+}
+
+#[test]
+fn rc_assignments_stay_in_the_shell() {
+    let sandbox = Sandbox::new();
+    let rc = sandbox.write("rc", "LOCAL=shell-only\nexport SHARED=exported\n");
+    let input = "echo \"[$LOCAL] [$SHARED]\"\nenv | grep -e ^LOCAL= -e ^SHARED=\n";
+    let run = sandbox.session(&["--rcfile", rc.to_str().unwrap()], input, &[]);
+    assert!(run.stdout.contains("[shell-only] [exported]\nSHARED=exported\n"), "{}", run.stdout);
+    assert!(!run.stdout.contains("LOCAL="), "{}", run.stdout);
+}
+
+#[test]
+fn recursive_source_is_refused() {
+    let sandbox = Sandbox::new();
+    sandbox.write("a", "echo in a\nsource b\necho end of a\n");
+    sandbox.write("b", "echo in b\nsource a\necho end of b\n");
+    let once = "in a\nin b\nError executing line 2 in b: 'source a': source: recursive include detected: a\nend of b\nend of a\n";
+    assert_eq!(sandbox.run("source a; echo $?").stdout, format!("{}0\n", once));
+    // Once done, the same file may be sourced again.
+    assert_eq!(sandbox.run("source a; . a").stdout, once.repeat(2));
+}
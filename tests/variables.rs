@@ -0,0 +1,75 @@
+mod common;
+
+use common::{falsh, output, Sandbox};
+
+#[test]
+fn export_appends() {
+    assert_eq!(falsh("export FOO=a; export FOO+=:b; echo $FOO; sh -c 'echo $FOO'"), "a:b\na:b\n");
+    assert_eq!(falsh("export CFLAGS+=\" -g\"; echo \"[$CFLAGS]\""), "[ -g]\n");
+    let run = Sandbox::new().run_with("export EXISTING+=2; sh -c 'echo $EXISTING'", &[("EXISTING", "1")]);
+    assert_eq!(run.stdout, "12\n");
+}
+
+#[test]
+fn export_rejects_invalid_names() {
+    assert_eq!(falsh("export =x; echo $?"), "export: '=x': not a valid identifier\n1\n");
+    assert_eq!(falsh("export +=x; echo $?"), "export: '+=x': not a valid identifier\n1\n");
+    assert_eq!(falsh("export 1A=x OK=y; echo $? $OK"), "export: '1A=x': not a valid identifier\n1 y\n");
+}
+
+#[test]
+fn prefixed_assignments_last_one_command() {
+    assert_eq!(falsh("FOO=bar sh -c 'echo $FOO'; echo \"[$FOO]\""), "bar\n[]\n");
+    // Builtins see them too, only while they run.
+    assert_eq!(falsh("FOO=bar export | grep FOO=; export | grep -c FOO="), "FOO=bar\n0\n");
+}
+
+#[test]
+fn bare_assignments_set_shell_variables() {
+    let run = Sandbox::new().run("FOO=bar; echo $FOO; A=1 B=2; echo $A$B");
+    assert_eq!(run.stdout, "bar\n12\n");
+    assert_eq!(run.status, 0);
+}
+
+#[test]
+fn random_is_in_range_and_changes() {
+    let out = falsh("echo $RANDOM $RANDOM $RANDOM $RANDOM");
+    let numbers: Vec<u32> = out.split_whitespace().map(|n| n.parse().unwrap()).collect();
+    assert_eq!(numbers.len(), 4);
+    assert!(numbers.iter().all(|&n| n <= 32767), "{:?}", numbers);
+    assert!(numbers.windows(2).any(|pair| pair[0] != pair[1]), "{:?}", numbers);
+}
+
+#[test]
+fn seconds_counts_from_start() {
+    assert_eq!(falsh("echo $SECONDS"), "0\n");
+    assert_eq!(falsh("sleep 1.1; echo $SECONDS"), "1\n");
+}
+
+#[test]
+fn dynamic_variables_are_not_exported() {
+    assert_eq!(falsh("echo $RANDOM >/dev/null; export | grep -c -e '^RANDOM=' -e '^SECONDS='"), "0\n");
+}
+
+#[test]
+fn substitutions_see_exported_variables_only() {
+    let out = falsh("export X=1; LOCAL=2; echo \"[$(printenv X)] [$(printenv LOCAL)]\"");
+    assert_eq!(out, "[1] []\n");
+    assert_eq!(falsh("LOCAL=2; echo $(echo $LOCAL)"), "2\n");
+}
+
+#[test]
+fn subshells_see_shell_variables_without_exporting_them() {
+    assert_eq!(falsh("X=1; (printenv X; echo \"[$X]\")"), "[1]\n");
+    assert_eq!(falsh("X=\"it's\nhere\"; (echo \"$X\") | cat"), "it's\nhere\n");
+}
+
+#[test]
+fn shift_drops_positional_parameters() {
+    let sandbox = Sandbox::new();
+    let script = "shift; echo \"$@\"; shift 2; echo \"[$*]\" $#; shift 0; echo $1";
+    let run = output(sandbox.command(&["--norc", "-c", script, "a", "b", "c", "d"]));
+    assert_eq!(run.stdout, "b c d\n[d] 1\nd\n");
+    let run = output(sandbox.command(&["--norc", "-c", "shift 3; echo $?; shift x; echo $? \"$@\"", "a", "b"]));
+    assert_eq!(run.stdout, "shift: 3: shift count out of range\n1\nshift: x: numeric argument required\n1 a b\n");
+}